@@ -145,6 +145,7 @@ const asyncGraphqlPlugin: CodegenPlugin<AsyncGraphqlPluginOptions> = {
     for (const enumType of enumTypes) {
       content += `
 #[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 pub enum ${enumType.name} {
   ${enumType
     .getValues()
@@ -1,7 +1,10 @@
+use std::collections::HashSet;
 use std::fs;
+use std::io;
+use std::path::Path;
 use std::vec;
 
-use chrono::TimeZone;
+use chrono::DateTime;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use tauri::api::dir::DiskEntry;
@@ -10,16 +13,50 @@ use tauri::api::{
   path::{document_dir, home_dir},
 };
 
+use crate::error::WadpunkError;
 use crate::graphql::generated::Game;
+use crate::graphql::generated::GameStatus;
 use crate::graphql::generated::SourcePort;
+use crate::library_index;
+use crate::library_issues;
+use crate::library_store::LibraryStore;
+use crate::migrations;
+use crate::migrations::Schema;
+
+#[cfg(test)]
+thread_local! {
+  // Tests run in parallel, each one gets a library of its own.
+  static TEST_DATA_DIRECTORY: std::cell::RefCell<Option<std::path::PathBuf>> =
+    const { std::cell::RefCell::new(None) };
+}
 
 pub fn get_data_directory() -> std::path::PathBuf {
-  let fallback_documents_directory = home_dir().unwrap().join("Documents");
-  let documents_directory = document_dir().unwrap_or(fallback_documents_directory);
+  #[cfg(test)]
+  if let Some(test_data_directory) = TEST_DATA_DIRECTORY.with(|x| x.borrow().clone()) {
+    return test_data_directory;
+  }
+
+  // Without a home directory either, it's next to where the app was started.
+  let documents_directory = document_dir()
+    .or_else(|| home_dir().map(|x| x.join("Documents")))
+    .unwrap_or_default();
 
   documents_directory.join("WADPunk")
 }
 
+/// Points the calling test at an empty library in the temp directory.
+#[cfg(test)]
+pub fn use_test_data_directory(test_name: &str) -> std::path::PathBuf {
+  let test_data_directory =
+    std::env::temp_dir().join(format!("wadpunk-{}-{}", test_name, std::process::id()));
+  let _ = fs::remove_dir_all(&test_data_directory);
+
+  TEST_DATA_DIRECTORY.with(|x| *x.borrow_mut() = Some(test_data_directory.clone()));
+  init_games().unwrap();
+
+  test_data_directory
+}
+
 pub fn get_games_directory() -> std::path::PathBuf {
   get_data_directory().join("Games")
 }
@@ -32,18 +69,124 @@ pub fn get_meta_directory() -> std::path::PathBuf {
   get_data_directory().join("Meta")
 }
 
-pub fn init_games() {
-  fs::create_dir_all(get_games_directory()).unwrap();
-  fs::create_dir_all(get_source_ports_directory()).unwrap();
-  fs::create_dir_all(get_meta_directory()).unwrap();
+// Metadata files that couldn't be read are moved here, see
+// `library_issues.rs`.
+pub fn get_quarantine_directory() -> std::path::PathBuf {
+  get_meta_directory().join(".quarantine")
+}
+
+pub fn get_game_saves_directory(game_id: &str) -> std::path::PathBuf {
+  get_meta_directory()
+    .join(normalize_name_from_id(game_id))
+    .join("saves")
+}
+
+// Where `importFromUrl` downloads to before importing.
+pub fn get_downloads_directory() -> std::path::PathBuf {
+  get_data_directory().join("Downloads")
+}
+
+// Built from an idgames listing or export by `loadIdgamesIndex`.
+pub fn get_idgames_index_path() -> std::path::PathBuf {
+  get_data_directory().join("idgamesIndex.json")
+}
+
+// An SQLite copy of the metadata in `Meta`, see `library_index.rs`.
+pub fn get_library_index_path() -> std::path::PathBuf {
+  get_data_directory().join("library.sqlite3")
+}
+
+pub fn get_settings_path() -> std::path::PathBuf {
+  get_data_directory().join("settings.json")
+}
+
+pub fn init_games() -> Result<(), WadpunkError> {
+  fs::create_dir_all(get_games_directory())?;
+  fs::create_dir_all(get_source_ports_directory())?;
+  fs::create_dir_all(get_meta_directory())?;
+
+  // Upgrades the files written by older versions, along with indexing them.
+  if let Err(err) = library_index::sync() {
+    library_issues::record_warning(None, format!("could not sync the library index: {}", err));
+  }
+  if let Err(err) = migrate_source_ports() {
+    library_issues::record_warning(None, format!("could not upgrade the source ports: {}", err));
+  }
+
+  Ok(())
+}
+
+pub fn find_all_games() -> Result<Vec<DbGameMeta>, WadpunkError> {
+  // Files can change while the app is running too, like by a sync tool. Only
+  // the ones that did are read.
+  let indexed_games = library_index::sync().and_then(|_| library_index::load_games());
+  let mut indexed_games = match indexed_games {
+    Ok(indexed_games) => indexed_games,
+    Err(err) => {
+      library_issues::record_warning(None, format!("could not read the library index: {}", err));
+      return find_all_games_from_files();
+    }
+  };
+
+  let mut db_games: Vec<DbGameMeta> = vec![];
+  let paths = read_dir(get_games_directory(), false)?;
+
+  for game_disk_entry in paths {
+    let Some(file_name) = disk_entry_name(&game_disk_entry) else {
+      continue;
+    };
+
+    if file_name.starts_with(".") {
+      continue;
+    }
+
+    let game_id = if game_disk_entry.children.is_some() {
+      format!("{}/", file_name)
+    } else {
+      file_name
+    };
+
+    // Games added to the folder by hand don't have metadata yet, loading it
+    // creates it, which indexes it.
+    let db_game = match indexed_games.remove(normalize_name_from_id(&game_id)) {
+      Some(Some(db_game)) => with_game_id(&game_id, db_game),
+      _ => load_game_meta(&game_id)?,
+    };
+
+    db_games.push(db_game)
+  }
+
+  // External games only exist as metadata.
+  for (name, db_game) in indexed_games {
+    let db_game = match db_game {
+      Some(db_game) => db_game,
+      None => match read_external_game_meta(&name)? {
+        Some(db_game) => db_game,
+        None => continue,
+      },
+    };
+    if db_game.external_path.is_none() {
+      continue;
+    }
+
+    let game_id = external_game_id(&name, &db_game);
+    db_games.push(with_game_id(&game_id, db_game))
+  }
+
+  Ok(db_games)
 }
 
-pub fn find_all_games() -> Vec<DbGameMeta> {
+/// Same as `find_all_games`, reading every game's metadata instead of using
+/// the library index.
+fn find_all_games_from_files() -> Result<Vec<DbGameMeta>, WadpunkError> {
   let mut db_games: Vec<DbGameMeta> = vec![];
-  let paths = read_dir(get_games_directory(), false).unwrap();
+  let mut game_names: HashSet<String> = HashSet::new();
+  let paths = read_dir(get_games_directory(), false)?;
 
   for game_disk_entry in paths {
-    let file_name = game_disk_entry.name.unwrap();
+    let Some(file_name) = disk_entry_name(&game_disk_entry) else {
+      continue;
+    };
 
     if file_name.starts_with(".") {
       continue;
@@ -55,10 +198,39 @@ pub fn find_all_games() -> Vec<DbGameMeta> {
       file_name
     };
 
-    db_games.push(load_game_meta(&game_id))
+    game_names.insert(normalize_name_from_id(&game_id).to_string());
+    db_games.push(load_game_meta(&game_id)?)
+  }
+
+  for meta_disk_entry in read_dir(get_meta_directory(), false)? {
+    let Some(name) = disk_entry_name(&meta_disk_entry) else {
+      continue;
+    };
+
+    if name.starts_with(".") || game_names.contains(&name) {
+      continue;
+    }
+
+    let Some(db_game) = read_external_game_meta(&name)? else {
+      continue;
+    };
+    if db_game.external_path.is_none() {
+      continue;
+    }
+
+    db_games.push(load_game_meta(&external_game_id(&name, &db_game))?)
   }
 
-  db_games
+  Ok(db_games)
+}
+
+/// Unlike in `load_game_meta`, the id from the file is needed here, it's the
+/// only thing that says whether an external game is a folder.
+fn external_game_id(name: &str, db_game: &DbGameMeta) -> String {
+  match &db_game.id {
+    Some(id) if normalize_name_from_id(id) == name => id.clone(),
+    _ => name.to_string(),
+  }
 }
 
 pub fn normalize_name_from_id(id: &str) -> &str {
@@ -69,33 +241,75 @@ pub fn normalize_name_from_id(id: &str) -> &str {
   }
 }
 
-pub fn load_game_meta(game_id: &str) -> DbGameMeta {
+pub fn load_game_meta(game_id: &str) -> Result<DbGameMeta, WadpunkError> {
   let json_meta_path = get_meta_directory().join(game_id).join("meta.json");
 
   if !json_meta_path.exists() {
-    let db_game = DbGameMeta {
-      id: Some(game_id.to_string()),
-      name: Some(normalize_name_from_id(game_id).to_string()),
-      rating: None,
-      description: None,
-      notes: None,
-      tags: None,
-      iwad_id: None,
-      source_port: None,
-      extra_mod_ids: None,
-      previous_file_state: None,
-      use_custom_config: None,
-
-      installed_at: Some(Utc::now().to_rfc3339()),
-    };
-
-    save_game(db_game);
+    save_game(default_game_meta(game_id))?;
   }
 
-  let json_contents = fs::read_to_string(json_meta_path).unwrap_or("{}".to_string());
+  let json_contents = fs::read_to_string(&json_meta_path)?;
+
+  // Sync tools and crashes mid-write leave broken files behind. The game
+  // starts over with fresh metadata rather than being stuck.
+  let db_game_meta = match parse_game_meta(&json_meta_path, &json_contents) {
+    Ok(db_game_meta) => db_game_meta,
+    Err(err @ WadpunkError::Parse { .. }) => {
+      library_issues::quarantine_game_meta(game_id, &json_meta_path, &err)?;
+
+      let db_game_meta = default_game_meta(game_id);
+      save_game(db_game_meta.clone())?;
+
+      db_game_meta
+    }
+    Err(err) => return Err(err),
+  };
+
+  Ok(with_game_id(game_id, db_game_meta))
+}
+
+fn parse_game_meta(json_meta_path: &Path, json_contents: &str) -> Result<DbGameMeta, WadpunkError> {
+  let json_contents = migrations::upgrade(json_meta_path, Schema::GameMeta, json_contents)?;
+
+  serde_json::from_str::<DbGameMeta>(&json_contents)
+    .map_err(|err| WadpunkError::parse(json_meta_path, err))
+}
 
-  let mut db_game_meta = serde_json::from_str::<DbGameMeta>(&json_contents).unwrap();
+/// The metadata a game starts out with, before anything was set on it.
+pub fn default_game_meta(game_id: &str) -> DbGameMeta {
+  DbGameMeta {
+    schema_version: migrations::GAME_META_SCHEMA_VERSION,
+    id: Some(game_id.to_string()),
+    name: Some(normalize_name_from_id(game_id).to_string()),
+    rating: None,
+    description: None,
+    notes: None,
+    tags: None,
+    author: None,
+    release_date: None,
+    requires_iwad: None,
+    idgames_id: None,
+    idgames_rating: None,
+    idgames_textfile: None,
+    idgames_path: None,
+    source_md5: None,
+    source_size: None,
+    external_path: None,
+    keep_archive: None,
+    archive_entries: None,
+    iwad_id: None,
+    iwad_id_inferred: None,
+    source_port: None,
+    extra_mod_ids: None,
+    previous_file_state: None,
+    use_custom_config: None,
+
+    installed_at: Utc::now().to_rfc3339(),
+    status: None,
+  }
+}
 
+fn with_game_id(game_id: &str, mut db_game_meta: DbGameMeta) -> DbGameMeta {
   // Intentionally never use the id from the file, use whatever is being passed
   // around.
   db_game_meta.id = Some(game_id.to_string());
@@ -107,128 +321,368 @@ pub fn load_game_meta(game_id: &str) -> DbGameMeta {
   db_game_meta
 }
 
-pub fn load_game_play_sessions(game_id: &str) -> DbPlaySession {
+/// The external path in a game's metadata, without creating the metadata
+/// when there is none.
+pub fn find_external_path(game_id: &str) -> Option<String> {
+  let json_meta_path = get_meta_directory().join(game_id).join("meta.json");
+  let json_contents = fs::read_to_string(&json_meta_path).ok()?;
+
+  parse_game_meta(&json_meta_path, &json_contents)
+    .ok()?
+    .external_path
+}
+
+/// Metadata without a game in the games directory, that's an external game
+/// when it has an `external_path`. There's nothing to start one over from when
+/// its file doesn't parse, it's quarantined so it shows up as a library issue
+/// and can be fixed by hand.
+fn read_external_game_meta(name: &str) -> Result<Option<DbGameMeta>, WadpunkError> {
+  let json_meta_path = get_meta_directory().join(name).join("meta.json");
+  let json_contents = match fs::read_to_string(&json_meta_path) {
+    Ok(json_contents) => json_contents,
+    // Quarantined already, the index catches up on the next sync.
+    Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+    Err(err) => return Err(WadpunkError::Io(err)),
+  };
+
+  match parse_game_meta(&json_meta_path, &json_contents) {
+    Ok(db_game_meta) => Ok(Some(db_game_meta)),
+    Err(err @ WadpunkError::Parse { .. }) => {
+      library_issues::quarantine_game_meta(name, &json_meta_path, &err)?;
+
+      Ok(None)
+    }
+    Err(err) => Err(err),
+  }
+}
+
+pub fn load_game_play_sessions(game_id: &str) -> Result<DbPlaySession, WadpunkError> {
+  let name = normalize_name_from_id(game_id);
+
+  if let Ok(Some(db_play_sessions)) = library_index::load_play_sessions(name) {
+    return Ok(db_play_sessions);
+  }
+
   let meta_path = get_meta_directory().join(game_id).join("playSessions.json");
-  let json_contents = fs::read_to_string(meta_path).unwrap_or("{}".to_string());
+  let json_contents = match fs::read_to_string(&meta_path) {
+    Ok(json_contents) => migrations::upgrade(&meta_path, Schema::PlaySessions, &json_contents)?,
+    // Indexed at the current version, so syncing leaves it alone.
+    Err(err) if err.kind() == io::ErrorKind::NotFound => serde_json::to_string(&DbPlaySession {
+      schema_version: migrations::PLAY_SESSIONS_SCHEMA_VERSION,
+      ..Default::default()
+    })
+    .unwrap(),
+    Err(err) => return Err(WadpunkError::Io(err)),
+  };
+
+  let db_play_sessions = serde_json::from_str::<DbPlaySession>(&json_contents)
+    .map_err(|err| WadpunkError::parse(&meta_path, err))?;
+
+  // Games that were never played are indexed too, so they aren't looked up
+  // again.
+  if let Err(err) = library_index::index_play_sessions(name, &json_contents) {
+    library_issues::record_warning(
+      Some(game_id),
+      format!("could not index the play sessions: {}", err),
+    );
+  }
 
-  serde_json::from_str::<DbPlaySession>(&json_contents).unwrap()
+  Ok(db_play_sessions)
 }
 
-pub fn record_game_play_session(game_id: &str, db_play_session_entry: DbPlaySessionEntry) {
+pub fn record_game_play_session(
+  game_id: &str,
+  db_play_session_entry: DbPlaySessionEntry,
+) -> Result<(), WadpunkError> {
   let file_path = get_meta_directory().join(game_id).join("playSessions.json");
 
-  let mut db_play_sessions = load_game_play_sessions(game_id);
-  let mut play_sessions_sessions = db_play_sessions.sessions.clone().unwrap_or_default();
-  play_sessions_sessions.push(db_play_session_entry.clone());
-  db_play_sessions.sessions = Some(play_sessions_sessions);
+  let mut db_play_sessions = load_game_play_sessions(game_id)?;
+  db_play_sessions.schema_version = migrations::PLAY_SESSIONS_SCHEMA_VERSION;
+  db_play_sessions.sessions.push(db_play_session_entry);
 
   let json_str = serde_json::to_string(&db_play_sessions).unwrap();
 
-  fs::create_dir_all(file_path.parent().unwrap()).unwrap();
-  fs::write(file_path, json_str).unwrap();
+  fs::create_dir_all(file_path.parent().unwrap())?;
+  fs::write(file_path, &json_str)?;
+
+  if let Err(err) = library_index::index_play_sessions(normalize_name_from_id(game_id), &json_str) {
+    library_issues::record_warning(
+      Some(game_id),
+      format!("could not index the play sessions: {}", err),
+    );
+  }
+
+  Ok(())
+}
+
+// Windows doesn't allow `:` in file names.
+const FILE_NAME_TIME_FORMAT: &str = "%Y-%m-%dT%H-%M-%S%.3fZ";
+
+/// A time the way it's put in file names, like save snapshots and
+/// quarantined metadata.
+pub fn format_file_name_time(time: &DateTime<Utc>) -> String {
+  time.format(FILE_NAME_TIME_FORMAT).to_string()
+}
+
+pub fn parse_file_name_time(text: &str) -> Option<DateTime<Utc>> {
+  chrono::NaiveDateTime::parse_from_str(text, FILE_NAME_TIME_FORMAT)
+    .ok()
+    .map(|x| x.and_utc())
+}
+
+/// A file in a game. Kept archives also list what a source port would load
+/// from inside them, like `maps/map01.wad`. Those are only for showing, they
+/// can't be passed to a port.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameFile {
+  pub path: String,
+  pub archive_entries: Vec<String>,
 }
 
-pub fn find_all_game_files(game_id: &str) -> Vec<String> {
+/// Every file in a folder game, or the file itself for games that are one
+/// file. `game_path` is where the game's files are, see
+/// `LibraryStore::get_game_path`. `archive_entries` go with a game that's
+/// one kept archive.
+pub fn find_game_files(
+  game_id: &str,
+  game_path: &Path,
+  archive_entries: Vec<String>,
+) -> Vec<GameFile> {
   let mut files: Vec<String> = vec![];
 
   if game_id.ends_with("/") {
-    let files_in_game_folder = read_dir(get_games_directory().join(game_id), true).unwrap();
+    // External games can be on a drive that isn't connected right now.
+    let files_in_game_folder = read_dir(game_path, true).unwrap_or_default();
 
     for file_disk_entry in files_in_game_folder {
       recurse_disk_entry(file_disk_entry, &mut files);
     }
   } else {
-    files.push(
-      get_games_directory()
-        .join(game_id)
-        .to_str()
-        .unwrap()
-        .to_string(),
-    );
+    push_game_file(&mut files, game_path);
+
+    return files
+      .into_iter()
+      .map(|path| GameFile {
+        path,
+        archive_entries: archive_entries.clone(),
+      })
+      .collect();
   }
 
   files
+    .into_iter()
+    .map(|path| GameFile {
+      path,
+      archive_entries: vec![],
+    })
+    .collect()
+}
+
+/// Files whose path isn't valid UTF-8 can't be loaded or shown, they're left
+/// out and recorded as an issue.
+fn push_game_file(files: &mut Vec<String>, path: &Path) {
+  match path_to_string(path) {
+    Ok(path) => files.push(path),
+    Err(err) => library_issues::record_warning(None, err.to_string()),
+  }
+}
+
+/// A path the way the metadata files and the frontend keep it, which can
+/// only be valid UTF-8.
+pub fn path_to_string(path: &Path) -> Result<String, WadpunkError> {
+  path
+    .to_str()
+    .map(|x| x.to_string())
+    .ok_or_else(|| WadpunkError::InvalidInput(format!("{:?} isn't a valid UTF-8 path", path)))
 }
 
-pub fn find_game_by_id(id: &str) -> Option<DbGameMeta> {
-  let db_game = load_game_meta(id);
-  return Some(db_game);
+/// A directory entry's name. Entries whose name isn't valid UTF-8 can't be
+/// games or source ports, they're left out and recorded as an issue.
+/// `DiskEntry::name` has those replaced, they'd name a file that isn't there.
+fn disk_entry_name(disk_entry: &DiskEntry) -> Option<String> {
+  let name = disk_entry
+    .path
+    .file_name()
+    .and_then(|x| x.to_str())
+    .map(|x| x.to_string());
+
+  if name.is_none() {
+    library_issues::record_warning(
+      None,
+      format!(
+        "{:?} was skipped, its name isn't valid UTF-8",
+        disk_entry.path
+      ),
+    );
+  }
+
+  name
+}
+
+/// A game file's path the way the game dialog shows it, starting with the
+/// game's name, like `mymap/maps/map01.wad`.
+pub fn get_relative_game_file_path(game_id: &str, game_path: &Path, absolute: &str) -> String {
+  let name = normalize_name_from_id(game_id);
+
+  match Path::new(absolute).strip_prefix(game_path) {
+    Ok(rest) if rest.as_os_str().is_empty() => name.to_string(),
+    // Both come from strings, so they're valid UTF-8.
+    Ok(rest) => Path::new(name).join(rest).to_string_lossy().to_string(),
+    Err(_) => absolute.to_string(),
+  }
 }
 
-pub fn save_game(db_game: DbGameMeta) {
-  let json_meta_path = get_meta_directory()
-    .join(normalize_name_from_id(&db_game.id.clone().unwrap()))
-    .join("meta.json");
+pub fn save_game(mut db_game: DbGameMeta) -> Result<(), WadpunkError> {
+  let game_id = db_game
+    .id
+    .clone()
+    .ok_or_else(|| WadpunkError::InvalidInput("game has no id".to_string()))?;
+  let name = normalize_name_from_id(&game_id);
+  let json_meta_path = get_meta_directory().join(name).join("meta.json");
 
+  db_game.schema_version = migrations::GAME_META_SCHEMA_VERSION;
   let json_str = serde_json::to_string(&db_game).unwrap();
 
-  fs::create_dir_all(json_meta_path.parent().unwrap()).unwrap();
-  fs::write(json_meta_path, json_str).unwrap();
+  fs::create_dir_all(json_meta_path.parent().unwrap())?;
+  fs::write(json_meta_path, &json_str)?;
+
+  if let Err(err) = library_index::index_game(name, &json_str) {
+    library_issues::record_warning(
+      Some(&game_id),
+      format!("could not index the metadata: {}", err),
+    );
+  }
+
+  Ok(())
 }
 
-pub fn find_all_source_ports() -> Vec<DbSourcePort> {
+pub fn find_all_source_ports() -> Result<Vec<DbSourcePort>, WadpunkError> {
   let mut db_source_ports: Vec<DbSourcePort> = vec![];
-  let paths = read_dir(get_source_ports_directory(), false).unwrap();
+  let paths = read_dir(get_source_ports_directory(), false)?;
 
   for source_port_disk_entry in paths {
-    let name_string = source_port_disk_entry.name.unwrap();
-    let name = name_string.as_str();
+    let Some(name) = disk_entry_name(&source_port_disk_entry) else {
+      continue;
+    };
 
     if name.starts_with(".") {
       continue;
     }
 
-    if !name.ends_with(".json") {
+    let Some(source_port_id) = name.strip_suffix(".json") else {
+      continue;
+    };
+    db_source_ports.push(find_source_port_by_id(source_port_id)?)
+  }
+
+  Ok(db_source_ports)
+}
+
+/// Source ports aren't in the library index, they're upgraded on their own
+/// when the library is synced.
+fn migrate_source_ports() -> Result<(), WadpunkError> {
+  for entry in fs::read_dir(get_source_ports_directory())?.flatten() {
+    let path = entry.path();
+    if path.extension().and_then(|x| x.to_str()) != Some("json") {
       continue;
     }
 
-    let source_port_id = name.strip_suffix(".json").unwrap().to_string();
-    db_source_ports.push(find_source_port_by_id(&source_port_id))
+    // Files that don't parse are reported when they're read.
+    if let Ok(contents) = fs::read_to_string(&path) {
+      let _ = migrations::migrate(&path, Schema::SourcePort, &contents);
+    }
   }
 
-  db_source_ports
+  Ok(())
 }
 
-pub fn find_source_port_by_id(source_port_id: &str) -> DbSourcePort {
+pub fn find_source_port_by_id(source_port_id: &str) -> Result<DbSourcePort, WadpunkError> {
   let json_path = get_source_ports_directory().join(format!("{}.json", source_port_id));
-  let json_contents = fs::read_to_string(json_path).unwrap_or("{}".to_string());
-
-  serde_json::from_str::<DbSourcePort>(&json_contents).unwrap()
+  let json_contents = fs::read_to_string(&json_path).map_err(|err| match err.kind() {
+    io::ErrorKind::NotFound => WadpunkError::NotFound(format!("source port {}", source_port_id)),
+    _ => WadpunkError::Io(err),
+  })?;
+  let json_contents = migrations::upgrade(&json_path, Schema::SourcePort, &json_contents)?;
+
+  let mut db_source_port = serde_json::from_str::<DbSourcePort>(&json_contents)
+    .map_err(|err| WadpunkError::parse(&json_path, err))?;
+  // Files written by hand might leave it out, it's the file's name.
+  db_source_port
+    .id
+    .get_or_insert_with(|| source_port_id.to_string());
+
+  Ok(db_source_port)
 }
 
-pub fn save_source_port(db_source_port: DbSourcePort) {
-  let json_path =
-    get_source_ports_directory().join(format!("{}.json", db_source_port.id.clone().unwrap()));
+pub fn save_source_port(mut db_source_port: DbSourcePort) -> Result<(), WadpunkError> {
+  let id = db_source_port
+    .id
+    .clone()
+    .ok_or_else(|| WadpunkError::InvalidInput("source port has no id".to_string()))?;
+  let json_path = get_source_ports_directory().join(format!("{}.json", id));
+
+  db_source_port.schema_version = migrations::SOURCE_PORT_SCHEMA_VERSION;
   let json_str = serde_json::to_string(&db_source_port).unwrap();
 
-  fs::write(json_path, json_str).unwrap();
+  fs::write(json_path, json_str)?;
+
+  Ok(())
 }
 
-pub fn delete_source_port(id: &str) {
+pub fn delete_source_port(id: &str) -> Result<(), WadpunkError> {
   let json_path = get_source_ports_directory().join(format!("{}.json", id));
-  fs::remove_file(json_path).unwrap();
+
+  fs::remove_file(json_path).map_err(|err| match err.kind() {
+    io::ErrorKind::NotFound => WadpunkError::NotFound(format!("source port {}", id)),
+    _ => WadpunkError::Io(err),
+  })
 }
 
-pub fn set_default_source_port(id: &str, is_default: Option<bool>) {
-  if let Some(is_default) = is_default {
-    if is_default {
-      let all_source_ports = find_all_source_ports();
+pub fn load_app_settings() -> Result<DbAppSettings, WadpunkError> {
+  let settings_path = get_settings_path();
+  let json_contents = read_to_string_or_empty(&settings_path)?;
 
-      for mut source_port in all_source_ports {
-        if source_port.id == Some(id.to_string()) {
-          source_port.is_default = Some(true);
-        } else {
-          source_port.is_default = Some(false);
-        }
+  serde_json::from_str::<DbAppSettings>(&json_contents)
+    .map_err(|err| WadpunkError::parse(&settings_path, err))
+}
 
-        save_source_port(source_port);
-      }
-    }
+pub fn save_app_settings(db_app_settings: DbAppSettings) -> Result<(), WadpunkError> {
+  let json_str = serde_json::to_string(&db_app_settings).unwrap();
+
+  fs::write(get_settings_path(), json_str)?;
+
+  Ok(())
+}
+
+/// Files that were never written read as an empty object, anything else that
+/// goes wrong is an error.
+pub fn read_to_string_or_empty(path: &Path) -> Result<String, WadpunkError> {
+  match fs::read_to_string(path) {
+    Ok(contents) => Ok(contents),
+    Err(err) if err.kind() == io::ErrorKind::NotFound => Ok("{}".to_string()),
+    Err(err) => Err(WadpunkError::Io(err)),
   }
 }
 
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct DbAppSettings {
+  // Relative URLs given to `importFromUrl` are resolved against this.
+  pub mirror_base_url: Option<String>,
+  pub idgames_api_url: Option<String>,
+
+  // Folders new downloads are imported from as they show up.
+  pub inbox_directories: Option<Vec<String>>,
+  // Where imported files are moved out of the inbox to. Relative to the inbox,
+  // when it isn't absolute. Left in place when not set.
+  pub inbox_processed_directory: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct DbGameMeta {
+  // See `migrations.rs`, 0 for files written before there was one.
+  #[serde(default)]
+  pub schema_version: u32,
+
   pub id: Option<String>,
   pub name: Option<String>,
   pub rating: Option<i32>,
@@ -236,35 +690,125 @@ pub struct DbGameMeta {
   pub notes: Option<String>,
   pub tags: Option<Vec<String>>,
 
+  pub author: Option<String>,
+  pub release_date: Option<String>,
+  pub requires_iwad: Option<String>,
+
+  pub idgames_id: Option<String>,
+  pub idgames_rating: Option<f32>,
+  pub idgames_textfile: Option<String>,
+  pub idgames_path: Option<String>,
+  // The file the game was imported from, usually an archive. That's what
+  // idgames and other indexes describe, not the files that came out of it.
+  pub source_md5: Option<String>,
+  pub source_size: Option<u64>,
+
+  // Set for games that were referenced where they are instead of being copied
+  // into the games directory.
+  pub external_path: Option<String>,
+  // Set for zip / 7z games that were imported without being extracted.
+  pub keep_archive: Option<bool>,
+  // What a source port would load from inside the kept archive. Listed on
+  // import, 7z archives can only be read with 7za.
+  pub archive_entries: Option<Vec<String>>,
+
   pub iwad_id: Option<String>,
+  // Set when the IWAD was picked on import rather than by the user, it's
+  // picked again when the game's files are overwritten.
+  pub iwad_id_inferred: Option<bool>,
   pub source_port: Option<String>,
   pub extra_mod_ids: Option<Vec<String>>,
   pub previous_file_state: Option<Vec<DbPreviousFileStateItem>>,
 
   pub use_custom_config: Option<bool>,
-  pub installed_at: Option<String>,
+  pub installed_at: String,
+
+  pub status: Option<GameStatus>,
 }
 
 impl DbGameMeta {
-  pub fn to_game(&self) -> Game {
-    Game {
-      id: self.id.clone().unwrap(),
+  pub fn to_game(&self, store: &dyn LibraryStore) -> Result<Game, WadpunkError> {
+    let id = self
+      .id
+      .clone()
+      .ok_or_else(|| WadpunkError::InvalidInput("game has no id".to_string()))?;
+
+    Ok(Game {
+      id,
       name: self.name.clone().unwrap_or_default(),
       rating: self.rating.unwrap_or_default(),
       description: self.description.clone().unwrap_or_default(),
       notes: self.notes.clone().unwrap_or_default(),
       tags: self.tags.clone().unwrap_or_default(),
 
+      author: self.author.clone().unwrap_or_default(),
+      release_date: self.release_date.clone().unwrap_or_default(),
+      requires_iwad: self.requires_iwad.clone(),
+
+      idgames_id: self.idgames_id.clone(),
+      idgames_rating: self.idgames_rating,
+      idgames_textfile: self.idgames_textfile.clone(),
+      idgames_path: self.idgames_path.clone(),
+      external_path: self.external_path.clone(),
+      keep_archive: self.keep_archive.unwrap_or_default(),
+
       iwad_id: Some(self.iwad_id.clone().unwrap_or_default()),
       source_port: Some(self.source_port.clone().unwrap_or_default()),
       extra_mod_ids: Some(self.extra_mod_ids.clone().unwrap_or_default()),
       // previous_file_state: self.previous_file_state.clone().unwrap_or_default(),
       use_custom_config: self.use_custom_config.unwrap_or_default(),
-      installed_at: self
-        .installed_at
-        .clone()
-        .unwrap_or(Utc.timestamp_opt(0, 0).unwrap().to_rfc3339()),
+      installed_at: self.installed_at.clone(),
+      status: self.resolve_status(store),
+    })
+  }
+
+  pub fn is_iwad(&self) -> bool {
+    self
+      .tags
+      .iter()
+      .flat_map(|tags| tags.iter())
+      .any(|tag| tag.to_lowercase() == "iwad")
+  }
+
+  /// Games without an explicit status are unplayed until they've been
+  /// played, playing while the last session is recent, and abandoned after
+  /// that. Beaten and replaying only come from `updateStatus`.
+  pub fn resolve_status(&self, store: &dyn LibraryStore) -> GameStatus {
+    if let Some(status) = self.status {
+      return status;
+    }
+
+    let db_play_sessions =
+      store.load_game_play_sessions_or_empty(self.id.as_deref().unwrap_or_default());
+
+    derive_status(&db_play_sessions)
+  }
+}
+
+// Games without a status count as abandoned once they haven't been played
+// for this long.
+const RECENT_PLAY_DAYS: i64 = 30;
+
+fn derive_status(db_play_sessions: &DbPlaySession) -> GameStatus {
+  if db_play_sessions.sessions.is_empty() {
+    return GameStatus::UNPLAYED;
+  }
+
+  let last_played_at = db_play_sessions
+    .sessions
+    .iter()
+    .filter_map(|x| DateTime::parse_from_rfc3339(x.started_at.as_deref()?).ok())
+    .max();
+
+  match last_played_at {
+    Some(last_played_at)
+      if Utc::now().signed_duration_since(last_played_at)
+        > chrono::Duration::days(RECENT_PLAY_DAYS) =>
+    {
+      GameStatus::ABANDONED
     }
+    // Sessions without a start time still mean the game was played.
+    _ => GameStatus::PLAYING,
   }
 }
 
@@ -277,47 +821,115 @@ pub struct DbPreviousFileStateItem {
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct DbPlaySession {
-  pub sessions: Option<Vec<DbPlaySessionEntry>>,
+  #[serde(default)]
+  pub schema_version: u32,
+
+  pub sessions: Vec<DbPlaySessionEntry>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct DbPlaySessionEntry {
   pub started_at: Option<String>,
   pub ended_at: Option<String>,
+
+  // Recorded at launch so stats don't depend on the game's current settings.
+  // Older sessions won't have these.
+  pub source_port: Option<String>,
+  pub iwad_id: Option<String>,
+}
+
+impl DbPlaySessionEntry {
+  /// Duration in seconds, or `None` if the session is missing either end or
+  /// has timestamps that can't be parsed.
+  pub fn duration(&self) -> Option<i64> {
+    let started_at = DateTime::parse_from_rfc3339(self.started_at.as_deref()?).ok()?;
+    let ended_at = DateTime::parse_from_rfc3339(self.ended_at.as_deref()?).ok()?;
+
+    Some((ended_at - started_at).num_seconds())
+  }
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct DbSourcePort {
+  #[serde(default)]
+  pub schema_version: u32,
+
   pub id: Option<String>,
   pub command: Option<Vec<String>>,
-  pub known_source_port_id: Option<String>,
+  pub known_source_port_id: String,
   pub is_default: Option<bool>,
 }
 
-fn recurse_disk_entry(dir: DiskEntry, files: &mut Vec<String>) {
+pub fn recurse_disk_entry(dir: DiskEntry, files: &mut Vec<String>) {
   if let Some(children) = dir.children {
     for d in children {
       recurse_disk_entry(d, files);
     }
   } else {
-    if dir.name.unwrap().starts_with(".") {
+    let Some(name) = disk_entry_name(&dir) else {
+      return;
+    };
+    if name.starts_with(".") {
       return;
     }
 
-    files.push(dir.path.to_str().unwrap().to_string());
+    push_game_file(files, &dir.path);
   }
 }
 
 impl DbSourcePort {
-  pub fn to_source_port(&self) -> SourcePort {
-    SourcePort {
-      id: self.id.clone().unwrap(),
+  pub fn to_source_port(&self) -> Result<SourcePort, WadpunkError> {
+    let id = self
+      .id
+      .clone()
+      .ok_or_else(|| WadpunkError::InvalidInput("source port has no id".to_string()))?;
+
+    Ok(SourcePort {
+      id,
       command: self.command.clone().unwrap_or_default(),
-      known_source_port_id: self
-        .known_source_port_id
-        .clone()
-        .unwrap_or("gzdoom".to_string()),
+      known_source_port_id: self.known_source_port_id.clone(),
       is_default: self.is_default.unwrap_or_default(),
-    }
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn names_source_ports_without_an_id_after_their_file() {
+    use_test_data_directory("source-port-without-id");
+    fs::write(
+      get_source_ports_directory().join("gzdoom.json"),
+      r#"{"command":["gzdoom"],"known_source_port_id":"gzdoom"}"#,
+    )
+    .unwrap();
+
+    let db_source_ports = find_all_source_ports().unwrap();
+
+    assert_eq!(db_source_ports.len(), 1);
+    assert_eq!(db_source_ports[0].to_source_port().unwrap().id, "gzdoom");
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn skips_games_whose_name_is_not_utf8() {
+    use std::os::unix::ffi::OsStrExt;
+
+    use_test_data_directory("non-utf8-game");
+    let name = std::ffi::OsStr::from_bytes(b"my\xffmap.wad");
+    fs::write(get_games_directory().join(name), "").unwrap();
+    fs::write(get_games_directory().join("mymap.wad"), "").unwrap();
+
+    let db_games = find_all_games().unwrap();
+
+    assert_eq!(
+      db_games.iter().map(|x| x.id.as_deref()).collect::<Vec<_>>(),
+      vec![Some("mymap.wad")]
+    );
+    assert!(library_issues::find_all_library_issues()
+      .iter()
+      .any(|x| x.message.contains("isn't valid UTF-8")));
   }
 }
@@ -0,0 +1,559 @@
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+use async_graphql::Error;
+use async_graphql::ErrorExtensions;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::database;
+use crate::error::to_graphql_error;
+
+pub const DEFAULT_MIRROR_BASE_URL: &str = "https://www.gamers.org/pub/idgames/";
+
+// Progress events are throttled, a fast connection would otherwise send
+// thousands of them.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+// A mirror that stops sending halfway fails the download instead of hanging
+// it, the partial file is kept for the next attempt.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub enum DownloadError {
+  Io(io::Error),
+  Http(String),
+  SizeMismatch { expected: u64, actual: u64 },
+  ChecksumMismatch { expected: String, actual: String },
+  InvalidChecksum(String),
+  InvalidUrl(String),
+}
+
+impl DownloadError {
+  /// Same as `WadpunkError::code`, with ones for the request failing and for
+  /// downloads that aren't what was expected.
+  pub fn code(&self) -> &'static str {
+    match self {
+      Self::Io(_) => "IO_ERROR",
+      Self::Http(_) => "NETWORK_ERROR",
+      Self::SizeMismatch { .. } | Self::ChecksumMismatch { .. } => "DOWNLOAD_ERROR",
+      Self::InvalidChecksum(_) | Self::InvalidUrl(_) => "INVALID_INPUT",
+    }
+  }
+}
+
+impl fmt::Display for DownloadError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Io(err) => write!(f, "{}", err),
+      Self::Http(message) => write!(f, "download failed: {}", message),
+      Self::SizeMismatch { expected, actual } => write!(
+        f,
+        "download is {} bytes, expected {} bytes",
+        actual, expected
+      ),
+      Self::ChecksumMismatch { expected, actual } => {
+        write!(f, "download has checksum {}, expected {}", actual, expected)
+      }
+      Self::InvalidChecksum(checksum) => {
+        write!(f, "{} is not an MD5 or SHA-256 checksum", checksum)
+      }
+      Self::InvalidUrl(url) => write!(f, "{} doesn't name a file to download", url),
+    }
+  }
+}
+
+impl ErrorExtensions for DownloadError {
+  fn extend(&self) -> Error {
+    to_graphql_error(self.to_string(), self.code())
+  }
+}
+
+impl From<io::Error> for DownloadError {
+  fn from(err: io::Error) -> Self {
+    Self::Io(err)
+  }
+}
+
+impl From<ureq::Error> for DownloadError {
+  fn from(err: ureq::Error) -> Self {
+    Self::Http(err.to_string())
+  }
+}
+
+/// Sent to the frontend while a download is running.
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadProgress {
+  pub url: String,
+  pub bytes_downloaded: u64,
+  pub total_bytes: Option<u64>,
+}
+
+pub fn get_mirror_base_url() -> String {
+  database::load_app_settings()
+    .unwrap_or_default()
+    .mirror_base_url
+    .filter(|x| !x.is_empty())
+    .unwrap_or(DEFAULT_MIRROR_BASE_URL.to_string())
+}
+
+/// Full URLs are used as they are, anything else is a path on the mirror,
+/// like `levels/doom2/a-c/av.zip`.
+pub fn resolve_url(url: &str, mirror_base_url: &str) -> String {
+  if url.starts_with("http://") || url.starts_with("https://") {
+    return url.to_string();
+  }
+
+  format!(
+    "{}/{}",
+    mirror_base_url.trim_end_matches('/'),
+    url.trim_start_matches('/')
+  )
+}
+
+/// Where a URL is downloaded to. Each URL gets its own folder, so two
+/// downloads with the same file name don't resume into each other. The file
+/// name is the last part of the URL's path, which tells the importer what it
+/// is, so URLs without one can't be downloaded.
+pub fn get_download_path(url: &str) -> Result<PathBuf, DownloadError> {
+  let url_hash = format!("{:x}", Sha256::digest(url.as_bytes()));
+
+  let file_name = url
+    .split(['?', '#'])
+    .next()
+    .and_then(|x| x.rsplit('/').next())
+    .map(percent_decode)
+    .filter(|x| !x.is_empty() && x != "." && x != ".." && !x.contains(['/', '\\']))
+    .ok_or_else(|| DownloadError::InvalidUrl(url.to_string()))?;
+
+  Ok(
+    database::get_downloads_directory()
+      .join(&url_hash[..16])
+      .join(file_name),
+  )
+}
+
+/// `%20` and the like back to what they stand for. Sequences that aren't
+/// valid are kept as they are.
+fn percent_decode(text: &str) -> String {
+  let bytes = text.as_bytes();
+  let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+
+  let mut index = 0;
+  while index < bytes.len() {
+    let hex = bytes
+      .get(index + 1..index + 3)
+      .and_then(|x| std::str::from_utf8(x).ok())
+      .and_then(|x| u8::from_str_radix(x, 16).ok());
+
+    match (bytes[index], hex) {
+      (b'%', Some(byte)) => {
+        decoded.push(byte);
+        index += 3;
+      }
+      (byte, _) => {
+        decoded.push(byte);
+        index += 1;
+      }
+    }
+  }
+
+  String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn agent() -> ureq::Agent {
+  ureq::AgentBuilder::new()
+    .timeout_connect(CONNECT_TIMEOUT)
+    .timeout_read(READ_TIMEOUT)
+    .build()
+}
+
+/// The first byte a `206 Partial Content` response starts at, from a
+/// `Content-Range` like `bytes 100-199/200`.
+fn content_range_start(response: &ureq::Response) -> Option<u64> {
+  response
+    .header("Content-Range")?
+    .strip_prefix("bytes ")?
+    .split('-')
+    .next()?
+    .trim()
+    .parse()
+    .ok()
+}
+
+/// Downloads `url` into the downloads folder, picking up where a previous
+/// attempt left off. `checksum` is a hex MD5 or SHA-256 digest.
+pub fn download<F>(
+  url: &str,
+  checksum: Option<&str>,
+  mut on_progress: F,
+) -> Result<PathBuf, DownloadError>
+where
+  F: FnMut(DownloadProgress),
+{
+  let checksum = checksum.map(|x| x.trim().to_lowercase());
+  if let Some(checksum) = &checksum {
+    if !is_valid_checksum(checksum) {
+      return Err(DownloadError::InvalidChecksum(checksum.clone()));
+    }
+  }
+
+  let download_path = get_download_path(url)?;
+  let mut partial_path = download_path.clone().into_os_string();
+  partial_path.push(".part");
+  let partial_path = PathBuf::from(partial_path);
+
+  fs::create_dir_all(download_path.parent().unwrap())?;
+
+  // Already downloaded by an earlier attempt that failed to import.
+  if !download_path.exists() {
+    let mut resume_from = fs::metadata(&partial_path).map(|x| x.len()).unwrap_or(0);
+
+    let agent = agent();
+    let mut request = agent.get(url);
+    if resume_from > 0 {
+      request = request.set("Range", &format!("bytes={}-", resume_from));
+    }
+
+    let mut response = match request.call() {
+      // The partial download is already complete, or the file changed.
+      // Either way, start over.
+      Err(ureq::Error::Status(416, _)) => {
+        resume_from = 0;
+        agent.get(url).call()?
+      }
+      response => response?,
+    };
+
+    // A range that doesn't start where the partial download ends can't be
+    // appended to it.
+    if response.status() == 206 && content_range_start(&response) != Some(resume_from) {
+      resume_from = 0;
+      response = agent.get(url).call()?;
+    }
+
+    // Servers that don't support ranges send the whole file again.
+    if response.status() != 206 {
+      resume_from = 0;
+    }
+
+    let total_bytes = response
+      .header("Content-Length")
+      .and_then(|x| x.parse::<u64>().ok())
+      .map(|x| x + resume_from);
+
+    let mut file = OpenOptions::new()
+      .create(true)
+      .write(true)
+      .append(resume_from > 0)
+      .truncate(resume_from == 0)
+      .open(&partial_path)?;
+
+    let mut reader = response.into_reader();
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut bytes_downloaded = resume_from;
+    let mut last_progress_at: Option<Instant> = None;
+
+    loop {
+      let read = match reader.read(&mut buffer) {
+        Ok(read) => read,
+        // The connection closed before all of `Content-Length` came in,
+        // that's checked below.
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => 0,
+        Err(err) => return Err(err.into()),
+      };
+      if read == 0 {
+        break;
+      }
+
+      file.write_all(&buffer[..read])?;
+      bytes_downloaded += read as u64;
+
+      let is_progress_due = match last_progress_at {
+        Some(last_progress_at) => last_progress_at.elapsed() >= PROGRESS_INTERVAL,
+        None => true,
+      };
+
+      if is_progress_due {
+        last_progress_at = Some(Instant::now());
+        on_progress(DownloadProgress {
+          url: url.to_string(),
+          bytes_downloaded,
+          total_bytes,
+        });
+      }
+    }
+
+    file.flush()?;
+
+    on_progress(DownloadProgress {
+      url: url.to_string(),
+      bytes_downloaded,
+      total_bytes,
+    });
+
+    // A short download is kept so the next attempt can resume it, a long one
+    // can't be trusted at all.
+    if let Some(total_bytes) = total_bytes {
+      if bytes_downloaded != total_bytes {
+        if bytes_downloaded > total_bytes {
+          fs::remove_file(&partial_path)?;
+        }
+
+        return Err(DownloadError::SizeMismatch {
+          expected: total_bytes,
+          actual: bytes_downloaded,
+        });
+      }
+    }
+
+    fs::rename(&partial_path, &download_path)?;
+  }
+
+  if let Some(checksum) = checksum {
+    let actual = file_checksum(&download_path, checksum.len())?;
+
+    if actual != checksum {
+      fs::remove_file(&download_path)?;
+
+      return Err(DownloadError::ChecksumMismatch {
+        expected: checksum,
+        actual,
+      });
+    }
+  }
+
+  Ok(download_path)
+}
+
+/// Removes a download's folder once it has been imported.
+pub fn remove_download(download_path: &Path) -> io::Result<()> {
+  fs::remove_dir_all(download_path.parent().unwrap())
+}
+
+fn is_valid_checksum(checksum: &str) -> bool {
+  (checksum.len() == 32 || checksum.len() == 64) && checksum.chars().all(|x| x.is_ascii_hexdigit())
+}
+
+/// MD5 for 32 character checksums, like idgames uses, SHA-256 otherwise.
+fn file_checksum(path: &Path, checksum_length: usize) -> io::Result<String> {
+  let mut file = File::open(path)?;
+  let mut buffer = vec![0u8; BUFFER_SIZE];
+
+  if checksum_length == 32 {
+    let mut context = md5::Context::new();
+    loop {
+      let read = file.read(&mut buffer)?;
+      if read == 0 {
+        break;
+      }
+      context.consume(&buffer[..read]);
+    }
+
+    Ok(format!("{:x}", context.compute()))
+  } else {
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::BufRead;
+  use std::io::BufReader;
+  use std::net::TcpListener;
+  use std::sync::Arc;
+  use std::sync::Mutex;
+
+  const CONTENTS: &[u8] = b"0123456789";
+
+  type Ranges = Arc<Mutex<Vec<Option<String>>>>;
+
+  /// Answers each request with what `respond` makes of its `Range` header,
+  /// and records the ranges asked for.
+  fn serve<F>(respond: F) -> (String, Ranges)
+  where
+    F: Fn(Option<&str>) -> Vec<u8> + Send + 'static,
+  {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/maps/mymap.zip", listener.local_addr().unwrap());
+    let ranges: Ranges = Arc::default();
+    let server_ranges = ranges.clone();
+
+    std::thread::spawn(move || {
+      for stream in listener.incoming() {
+        let mut stream = stream.unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+        let mut range = None;
+        loop {
+          let mut line = String::new();
+          reader.read_line(&mut line).unwrap();
+          if line.trim().is_empty() {
+            break;
+          }
+          if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("range") {
+              range = Some(value.trim().to_string());
+            }
+          }
+        }
+
+        server_ranges.lock().unwrap().push(range.clone());
+        stream.write_all(&respond(range.as_deref())).unwrap();
+      }
+    });
+
+    (url, ranges)
+  }
+
+  fn response(status: &str, headers: &[String], body: &[u8]) -> Vec<u8> {
+    let mut response = format!("HTTP/1.1 {}\r\nConnection: close\r\n", status);
+    for header in headers {
+      response.push_str(&format!("{}\r\n", header));
+    }
+    response.push_str("\r\n");
+
+    let mut response = response.into_bytes();
+    response.extend_from_slice(body);
+    response
+  }
+
+  fn whole_file() -> Vec<u8> {
+    response(
+      "200 OK",
+      &[format!("Content-Length: {}", CONTENTS.len())],
+      CONTENTS,
+    )
+  }
+
+  fn write_partial_download(url: &str, contents: &[u8]) {
+    let download_path = get_download_path(url).unwrap();
+    fs::create_dir_all(download_path.parent().unwrap()).unwrap();
+
+    let mut partial_path = download_path.into_os_string();
+    partial_path.push(".part");
+    fs::write(partial_path, contents).unwrap();
+  }
+
+  #[test]
+  fn resumes_partial_downloads() {
+    database::use_test_data_directory("download-resume");
+    let (url, ranges) = serve(|range| match range {
+      Some("bytes=5-") => response(
+        "206 Partial Content",
+        &[
+          "Content-Range: bytes 5-9/10".to_string(),
+          "Content-Length: 5".to_string(),
+        ],
+        &CONTENTS[5..],
+      ),
+      _ => whole_file(),
+    });
+    write_partial_download(&url, &CONTENTS[..5]);
+
+    let download_path = download(&url, None, |_| {}).unwrap();
+
+    assert_eq!(fs::read(download_path).unwrap(), CONTENTS);
+    assert_eq!(*ranges.lock().unwrap(), vec![Some("bytes=5-".to_string())]);
+  }
+
+  #[test]
+  fn starts_over_when_the_range_is_not_the_one_asked_for() {
+    database::use_test_data_directory("download-wrong-range");
+    let (url, ranges) = serve(|range| match range {
+      Some(_) => response(
+        "206 Partial Content",
+        &[
+          "Content-Range: bytes 0-9/10".to_string(),
+          "Content-Length: 10".to_string(),
+        ],
+        CONTENTS,
+      ),
+      None => whole_file(),
+    });
+    write_partial_download(&url, &CONTENTS[..5]);
+
+    let download_path = download(&url, None, |_| {}).unwrap();
+
+    assert_eq!(fs::read(download_path).unwrap(), CONTENTS);
+    assert_eq!(
+      *ranges.lock().unwrap(),
+      vec![Some("bytes=5-".to_string()), None]
+    );
+  }
+
+  #[test]
+  fn keeps_short_downloads_to_resume_later() {
+    database::use_test_data_directory("download-short");
+    let (url, _) = serve(|_| response("200 OK", &["Content-Length: 10".to_string()], b"01234"));
+
+    let result = download(&url, None, |_| {});
+
+    assert!(matches!(
+      result,
+      Err(DownloadError::SizeMismatch {
+        expected: 10,
+        actual: 5
+      })
+    ));
+    let mut partial_path = get_download_path(&url).unwrap().into_os_string();
+    partial_path.push(".part");
+    assert_eq!(fs::read(partial_path).unwrap(), b"01234");
+  }
+
+  #[test]
+  fn removes_downloads_with_the_wrong_checksum() {
+    database::use_test_data_directory("download-checksum");
+    let (url, _) = serve(|_| whole_file());
+    let checksum = format!("{:x}", md5::compute(b"something else"));
+
+    let result = download(&url, Some(&checksum), |_| {});
+
+    assert!(matches!(
+      result,
+      Err(DownloadError::ChecksumMismatch { .. })
+    ));
+    assert!(!get_download_path(&url).unwrap().exists());
+
+    let checksum = format!("{:x}", md5::compute(CONTENTS));
+    assert!(download(&url, Some(&checksum), |_| {}).is_ok());
+  }
+
+  #[test]
+  fn names_downloads_after_the_url() {
+    let file_name = |url: &str| {
+      get_download_path(url)
+        .ok()
+        .and_then(|x| x.file_name().map(|x| x.to_string_lossy().to_string()))
+    };
+
+    assert_eq!(
+      file_name("https://example.com/my%20map.zip?mirror=1").as_deref(),
+      Some("my map.zip")
+    );
+    assert_eq!(
+      file_name("https://example.com/100%.zip").as_deref(),
+      Some("100%.zip")
+    );
+    assert_eq!(file_name("https://example.com/maps/"), None);
+    assert_eq!(file_name("https://example.com/?id=1"), None);
+    assert_eq!(file_name("https://example.com/..%2Fmymap.zip"), None);
+  }
+}
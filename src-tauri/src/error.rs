@@ -0,0 +1,92 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use async_graphql::Error;
+use async_graphql::ErrorExtensionValues;
+use async_graphql::ErrorExtensions;
+
+#[derive(Debug)]
+pub enum WadpunkError {
+  Io(io::Error),
+  Parse { path: PathBuf, message: String },
+  NotFound(String),
+  InvalidInput(String),
+}
+
+impl WadpunkError {
+  pub fn parse(path: &Path, err: serde_json::Error) -> Self {
+    Self::Parse {
+      path: path.to_path_buf(),
+      message: err.to_string(),
+    }
+  }
+
+  /// Sent to the frontend as `extensions.code`.
+  pub fn code(&self) -> &'static str {
+    match self {
+      Self::Io(_) => "IO_ERROR",
+      Self::Parse { .. } => "PARSE_ERROR",
+      Self::NotFound(_) => "NOT_FOUND",
+      Self::InvalidInput(_) => "INVALID_INPUT",
+    }
+  }
+}
+
+impl fmt::Display for WadpunkError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Io(err) => write!(f, "{}", err),
+      Self::Parse { path, message } => write!(f, "could not parse {:?}: {}", path, message),
+      Self::NotFound(what) => write!(f, "{} not found", what),
+      Self::InvalidInput(message) => write!(f, "{}", message),
+    }
+  }
+}
+
+impl From<io::Error> for WadpunkError {
+  fn from(err: io::Error) -> Self {
+    Self::Io(err)
+  }
+}
+
+impl From<tauri::api::Error> for WadpunkError {
+  fn from(err: tauri::api::Error) -> Self {
+    match err {
+      tauri::api::Error::Io(err) => Self::Io(err),
+      err => Self::Io(io::Error::other(err.to_string())),
+    }
+  }
+}
+
+impl From<zip::result::ZipError> for WadpunkError {
+  fn from(err: zip::result::ZipError) -> Self {
+    Self::Io(err.into())
+  }
+}
+
+impl ErrorExtensions for WadpunkError {
+  fn extend(&self) -> Error {
+    to_graphql_error(self.to_string(), self.code())
+  }
+}
+
+/// A GraphQL error the frontend can tell apart by its `code`, without
+/// matching on the message.
+pub fn to_graphql_error(message: String, code: &str) -> Error {
+  let mut extensions = ErrorExtensionValues::default();
+  extensions.set("code", code);
+
+  Error {
+    message,
+    source: None,
+    extensions: Some(extensions),
+  }
+}
+
+/// Work moved off the async runtime that never finished, because it panicked
+/// or was cancelled.
+pub fn to_join_graphql_error(err: tauri::Error) -> Error {
+  to_graphql_error(err.to_string(), "INTERNAL_ERROR")
+}
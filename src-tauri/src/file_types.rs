@@ -0,0 +1,87 @@
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+// Enough to reach tar's "ustar" magic, which is the furthest in.
+const SNIFF_SIZE: u64 = 262;
+
+// These are zip / 7z archives that source ports load directly, so they are
+// kept as they are instead of being extracted.
+const LOADABLE_ARCHIVE_EXTENSIONS: [&str; 6] = ["pk3", "pk7", "ipk3", "ipk7", "pke", "pkz"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+  Zip,
+  SevenZip,
+  Rar,
+  Gzip,
+  Bzip2,
+  Xz,
+  Zstd,
+  Tar,
+  Iwad,
+  Pwad,
+  Unknown,
+}
+
+impl FileType {
+  pub fn is_wad(&self) -> bool {
+    matches!(self, Self::Iwad | Self::Pwad)
+  }
+}
+
+/// Detects a file's type from its first bytes, ignoring its extension.
+pub fn detect_file_type(path: &Path) -> io::Result<FileType> {
+  let mut header = vec![];
+  File::open(path)?
+    .take(SNIFF_SIZE)
+    .read_to_end(&mut header)?;
+
+  Ok(detect_file_type_from_header(&header))
+}
+
+pub fn detect_file_type_from_header(header: &[u8]) -> FileType {
+  if header.starts_with(b"PK\x03\x04")
+    || header.starts_with(b"PK\x05\x06")
+    || header.starts_with(b"PK\x07\x08")
+  {
+    FileType::Zip
+  } else if header.starts_with(b"7z\xBC\xAF\x27\x1C") {
+    FileType::SevenZip
+  } else if header.starts_with(b"Rar!\x1A\x07") {
+    FileType::Rar
+  } else if header.starts_with(b"\x1F\x8B") {
+    FileType::Gzip
+  } else if header.starts_with(b"BZh") {
+    FileType::Bzip2
+  } else if header.starts_with(b"\xFD7zXZ\x00") {
+    FileType::Xz
+  } else if header.starts_with(b"\x28\xB5\x2F\xFD") {
+    FileType::Zstd
+  } else if header.starts_with(b"IWAD") {
+    FileType::Iwad
+  } else if header.starts_with(b"PWAD") {
+    FileType::Pwad
+  } else if header.get(257..262) == Some(b"ustar".as_slice()) {
+    FileType::Tar
+  } else {
+    FileType::Unknown
+  }
+}
+
+pub fn is_loadable_archive(path: &Path) -> bool {
+  path
+    .extension()
+    .and_then(|x| x.to_str())
+    .map(|x| LOADABLE_ARCHIVE_EXTENSIONS.contains(&x.to_lowercase().as_str()))
+    .unwrap_or_default()
+}
+
+pub fn has_wad_extension(path: &Path) -> bool {
+  path
+    .extension()
+    .and_then(|x| x.to_str())
+    .map(|x| ["wad", "iwad", "pwad"].contains(&x.to_lowercase().as_str()))
+    .unwrap_or_default()
+}
@@ -1,89 +1,125 @@
-use std::fs;
 use std::path::Path;
 use std::process::Command;
 use std::vec;
 
 use async_graphql::Context;
-use async_graphql::Error;
+use async_graphql::ErrorExtensions;
 use async_graphql::Result as GraphQLResult;
-use chrono::DateTime;
 use chrono::Utc;
 
 use plist;
 use tauri::AppHandle;
+use tauri::Manager;
 
 use crate::database;
-use crate::database::normalize_name_from_id;
-use crate::database::DbPlaySession;
 use crate::database::DbPlaySessionEntry;
 use crate::database::DbPreviousFileStateItem;
 use crate::database::DbSourcePort;
+use crate::downloader;
+use crate::error::to_join_graphql_error;
+use crate::error::WadpunkError;
 use crate::graphql::generated::AppInfo;
+use crate::idgames;
+use crate::idgames_index;
 use crate::importer;
+use crate::inbox_watcher;
 use crate::known_source_ports;
 use crate::known_source_ports::find_known_source_port_from_id;
 use crate::known_source_ports::BuildCommandArgs;
+use crate::library_issues;
+use crate::library_stats;
+use crate::library_stats::StatsPeriod;
+use crate::library_store::LibraryStore;
+use crate::library_store::SharedLibraryStore;
+use crate::migrations;
+use crate::saves;
+use crate::store_installs;
 use crate::tauri_helpers::reveal_in_finder::reveal_file_or_folder;
 
 use super::generated::AppSettings;
+use super::generated::AppSettingsInput;
 use super::generated::CreateSourcePortInput;
 use super::generated::Game;
 use super::generated::GameFileEntry;
 use super::generated::GameInput;
+use super::generated::GamePlayTime;
+use super::generated::GameStatus;
+use super::generated::IdgamesDirectory;
+use super::generated::IdgamesFile;
+use super::generated::IdgamesIndexMatch;
+use super::generated::IdgamesSearchType;
+use super::generated::ImportConflictPolicy;
+use super::generated::ImportResult;
+use super::generated::ImportSummary;
+use super::generated::InstalledIwad;
 use super::generated::KnownSourcePort;
+use super::generated::LibraryIssue;
+use super::generated::LibraryPlaySession;
+use super::generated::LibraryStats;
 use super::generated::Mutation;
 use super::generated::PlaySession;
+use super::generated::PlayTimeGroup;
 use super::generated::PreviousFileStateItem;
 use super::generated::Query;
+use super::generated::SaveGame;
+use super::generated::SaveSnapshot;
 use super::generated::SourcePort;
 use super::generated::UpdateSourcePortInput;
 
 pub struct DataSource;
 
+/// The store `main` put in the schema's context.
+fn library_store<'a>(ctx: &Context<'a>) -> &'a dyn LibraryStore {
+  ctx.data_unchecked::<SharedLibraryStore>().as_ref()
+}
+
+/// Same as `library_store`, for work moved off the async runtime.
+fn shared_library_store(ctx: &Context<'_>) -> SharedLibraryStore {
+  ctx.data_unchecked::<SharedLibraryStore>().clone()
+}
+
 impl DataSource {
   pub async fn Game_play_sessions(
     &self,
     root: &Game,
-    _ctx: &Context<'_>,
+    ctx: &Context<'_>,
   ) -> GraphQLResult<Vec<PlaySession>> {
+    let store = library_store(ctx);
+
     let mut gql_play_sessions: Vec<PlaySession> = vec![];
 
-    let meta_path = database::get_meta_directory()
-      .join(normalize_name_from_id(&root.id))
-      .join("playSessions.json");
-    let json_contents = fs::read_to_string(meta_path).unwrap_or("{}".to_string());
-    let play_session_meta = serde_json::from_str::<DbPlaySession>(&json_contents).unwrap();
-
-    if let Some(play_sessions) = play_session_meta.sessions {
-      for play_session in play_sessions {
-        if let Some(started_at) = play_session.started_at {
-          if let Some(ended_at) = play_session.ended_at {
-            let duration: i32 = (DateTime::parse_from_rfc3339(&ended_at).unwrap()
-              - DateTime::parse_from_rfc3339(&started_at).unwrap())
-            .num_seconds()
-            .try_into()
-            .unwrap();
-
-            gql_play_sessions.push(PlaySession {
-              duration,
-              ended_at,
-              started_at,
-            })
-          }
-        }
+    let play_session_meta = store
+      .load_game_play_sessions(&root.id)
+      .map_err(|err| err.extend())?;
+
+    for play_session in play_session_meta.sessions {
+      if let Some(duration) = play_session.duration() {
+        gql_play_sessions.push(PlaySession {
+          duration: duration.try_into().unwrap(),
+          ended_at: play_session.ended_at.unwrap(),
+          started_at: play_session.started_at.unwrap(),
+        })
       }
     }
 
     Ok(gql_play_sessions)
   }
 
+  pub async fn Game_saves(&self, root: &Game, _ctx: &Context<'_>) -> GraphQLResult<Vec<SaveGame>> {
+    Ok(saves::find_all_game_saves(&root.id))
+  }
+
   pub async fn Game_previous_file_state(
     &self,
     root: &Game,
-    _ctx: &Context<'_>,
+    ctx: &Context<'_>,
   ) -> GraphQLResult<Vec<PreviousFileStateItem>> {
+    let store = library_store(ctx);
+
+    let db_game = store.load_game_meta(&root.id).map_err(|err| err.extend())?;
+
     Ok(
-      database::load_game_meta(&root.id)
+      db_game
         .previous_file_state
         .into_iter()
         .flatten()
@@ -96,13 +132,158 @@ impl DataSource {
     )
   }
 
+  // These share the play sessions `getLibraryStats` loaded.
+  pub async fn LibraryStats_by_day(
+    &self,
+    _root: &LibraryStats,
+    ctx: &Context<'_>,
+  ) -> GraphQLResult<Vec<PlayTimeGroup>> {
+    let store = library_store(ctx);
+
+    let play_sessions =
+      library_stats::loaded_stats_play_sessions(store).map_err(|err| err.extend())?;
+
+    Ok(library_stats::group_by_period(
+      &play_sessions.entries,
+      StatsPeriod::Day,
+    ))
+  }
+
+  pub async fn LibraryStats_by_week(
+    &self,
+    _root: &LibraryStats,
+    ctx: &Context<'_>,
+  ) -> GraphQLResult<Vec<PlayTimeGroup>> {
+    let store = library_store(ctx);
+
+    let play_sessions =
+      library_stats::loaded_stats_play_sessions(store).map_err(|err| err.extend())?;
+
+    Ok(library_stats::group_by_period(
+      &play_sessions.entries,
+      StatsPeriod::Week,
+    ))
+  }
+
+  pub async fn LibraryStats_by_month(
+    &self,
+    _root: &LibraryStats,
+    ctx: &Context<'_>,
+  ) -> GraphQLResult<Vec<PlayTimeGroup>> {
+    let store = library_store(ctx);
+
+    let play_sessions =
+      library_stats::loaded_stats_play_sessions(store).map_err(|err| err.extend())?;
+
+    Ok(library_stats::group_by_period(
+      &play_sessions.entries,
+      StatsPeriod::Month,
+    ))
+  }
+
+  pub async fn LibraryStats_by_source_port(
+    &self,
+    _root: &LibraryStats,
+    ctx: &Context<'_>,
+  ) -> GraphQLResult<Vec<PlayTimeGroup>> {
+    let store = library_store(ctx);
+
+    let play_sessions =
+      library_stats::loaded_stats_play_sessions(store).map_err(|err| err.extend())?;
+
+    Ok(library_stats::group_by_source_port(&play_sessions.entries))
+  }
+
+  pub async fn LibraryStats_by_tag(
+    &self,
+    _root: &LibraryStats,
+    ctx: &Context<'_>,
+  ) -> GraphQLResult<Vec<PlayTimeGroup>> {
+    let store = library_store(ctx);
+
+    let play_sessions =
+      library_stats::loaded_stats_play_sessions(store).map_err(|err| err.extend())?;
+
+    Ok(library_stats::group_by_tag(&play_sessions.entries))
+  }
+
+  pub async fn LibraryStats_by_iwad(
+    &self,
+    _root: &LibraryStats,
+    ctx: &Context<'_>,
+  ) -> GraphQLResult<Vec<PlayTimeGroup>> {
+    let store = library_store(ctx);
+
+    let play_sessions =
+      library_stats::loaded_stats_play_sessions(store).map_err(|err| err.extend())?;
+
+    Ok(library_stats::group_by_iwad(&play_sessions.entries))
+  }
+
+  pub async fn LibraryStats_longest_sessions(
+    &self,
+    _root: &LibraryStats,
+    ctx: &Context<'_>,
+    limit: Option<i32>,
+  ) -> GraphQLResult<Vec<LibraryPlaySession>> {
+    let store = library_store(ctx);
+
+    let play_sessions =
+      library_stats::loaded_stats_play_sessions(store).map_err(|err| err.extend())?;
+
+    Ok(library_stats::longest_sessions(
+      &play_sessions.entries,
+      limit.unwrap_or(10).max(0) as usize,
+    ))
+  }
+
+  pub async fn LibraryStats_most_played_games(
+    &self,
+    _root: &LibraryStats,
+    ctx: &Context<'_>,
+    limit: Option<i32>,
+  ) -> GraphQLResult<Vec<GamePlayTime>> {
+    let store = library_store(ctx);
+
+    let play_sessions =
+      library_stats::loaded_stats_play_sessions(store).map_err(|err| err.extend())?;
+
+    Ok(library_stats::most_played_games(
+      &play_sessions.db_games,
+      &play_sessions.entries,
+      limit.unwrap_or(10).max(0) as usize,
+    ))
+  }
+
+  pub async fn LibraryStats_never_launched_games(
+    &self,
+    _root: &LibraryStats,
+    ctx: &Context<'_>,
+  ) -> GraphQLResult<Vec<Game>> {
+    let store = library_store(ctx);
+
+    let play_sessions =
+      library_stats::loaded_stats_play_sessions(store).map_err(|err| err.extend())?;
+
+    library_stats::never_launched_games(&play_sessions.db_games, &play_sessions.entries)
+      .into_iter()
+      .map(|x| x.to_game(store))
+      .collect::<Result<_, _>>()
+      .map_err(|err| err.extend())
+  }
+
   pub async fn Query_getGame(
     &self,
     _root: &Query,
-    _ctx: &Context<'_>,
+    ctx: &Context<'_>,
     id: String,
   ) -> GraphQLResult<Game> {
-    Ok(database::load_game_meta(&id).to_game())
+    let store = library_store(ctx);
+
+    store
+      .find_game_by_id(&id)
+      .and_then(|x| x.to_game(store))
+      .map_err(|err| err.extend())
   }
 
   pub async fn Query_getAppSettings(
@@ -110,31 +291,38 @@ impl DataSource {
     _root: &Query,
     _ctx: &Context<'_>,
   ) -> GraphQLResult<AppSettings> {
+    let db_app_settings = database::load_app_settings().map_err(|err| err.extend())?;
+
     Ok(AppSettings {
-      dataDirectory: String::from(database::get_data_directory().to_str().unwrap()),
+      dataDirectory: database::get_data_directory().to_string_lossy().to_string(),
+      mirror_base_url: downloader::get_mirror_base_url(),
+      idgames_api_url: idgames::get_idgames_api_url(),
+      inbox_directories: db_app_settings.inbox_directories.unwrap_or_default(),
+      inbox_processed_directory: db_app_settings
+        .inbox_processed_directory
+        .filter(|x| !x.is_empty()),
     })
   }
 
   pub async fn Query_getGameFiles(
     &self,
     _root: &Query,
-    _ctx: &Context<'_>,
+    ctx: &Context<'_>,
     game_ids: Vec<String>,
   ) -> GraphQLResult<Vec<GameFileEntry>> {
+    let store = library_store(ctx);
+
     let mut game_file_entries: Vec<GameFileEntry> = vec![];
 
     for game_id in game_ids {
-      let game_files = database::find_all_game_files(&game_id);
+      let game_files = store.find_all_game_files(&game_id);
+      let game_path = store.get_game_path(&game_id);
 
       for game_file in game_files {
         game_file_entries.push(GameFileEntry {
-          absolute: game_file.clone(),
-          relative: Path::new(&game_file)
-            .strip_prefix(database::get_games_directory())
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .to_string(),
+          relative: database::get_relative_game_file_path(&game_id, &game_path, &game_file.path),
+          absolute: game_file.path,
+          archive_entries: game_file.archive_entries,
         })
       }
     }
@@ -142,17 +330,114 @@ impl DataSource {
     Ok(game_file_entries)
   }
 
-  pub async fn Query_getGames(
+  pub async fn Query_findInstalledIwads(
+    &self,
+    _root: &Query,
+    ctx: &Context<'_>,
+  ) -> GraphQLResult<Vec<InstalledIwad>> {
+    let store = shared_library_store(ctx);
+
+    tauri::async_runtime::spawn_blocking(move || {
+      store_installs::find_installed_iwads(store.as_ref())
+    })
+    .await
+    .map_err(to_join_graphql_error)?
+    .map_err(|err| err.extend())
+  }
+
+  pub async fn Query_searchIdgames(
     &self,
     _root: &Query,
     _ctx: &Context<'_>,
+    query: String,
+    search_type: Option<IdgamesSearchType>,
+  ) -> GraphQLResult<Vec<IdgamesFile>> {
+    let search_type = search_type.unwrap_or(IdgamesSearchType::TITLE);
+
+    run_idgames_request(move || idgames::search(&query, search_type)).await
+  }
+
+  pub async fn Query_getIdgamesDirectories(
+    &self,
+    _root: &Query,
+    _ctx: &Context<'_>,
+    name: String,
+  ) -> GraphQLResult<Vec<IdgamesDirectory>> {
+    run_idgames_request(move || idgames::get_directories(&name)).await
+  }
+
+  pub async fn Query_getIdgamesFiles(
+    &self,
+    _root: &Query,
+    _ctx: &Context<'_>,
+    name: String,
+  ) -> GraphQLResult<Vec<IdgamesFile>> {
+    run_idgames_request(move || idgames::get_files(&name)).await
+  }
+
+  pub async fn Query_getIdgamesFile(
+    &self,
+    _root: &Query,
+    _ctx: &Context<'_>,
+    id: String,
+  ) -> GraphQLResult<IdgamesFile> {
+    run_idgames_request(move || idgames::get_file(&id)).await
+  }
+
+  pub async fn Query_getLibraryStats(
+    &self,
+    _root: &Query,
+    ctx: &Context<'_>,
+  ) -> GraphQLResult<LibraryStats> {
+    let store = library_store(ctx);
+
+    let play_sessions =
+      library_stats::load_stats_play_sessions(store).map_err(|err| err.extend())?;
+
+    Ok(LibraryStats {
+      total_play_time: library_stats::total_play_time(&play_sessions.entries)
+        .try_into()
+        .unwrap_or(i32::MAX),
+      session_count: play_sessions.entries.len().try_into().unwrap_or(i32::MAX),
+    })
+  }
+
+  pub async fn Query_getLibraryIssues(
+    &self,
+    _root: &Query,
+    _ctx: &Context<'_>,
+  ) -> GraphQLResult<Vec<LibraryIssue>> {
+    Ok(library_issues::find_all_library_issues())
+  }
+
+  pub async fn Query_listSaveSnapshots(
+    &self,
+    _root: &Query,
+    _ctx: &Context<'_>,
+    game_id: String,
+  ) -> GraphQLResult<Vec<SaveSnapshot>> {
+    Ok(saves::find_all_save_snapshots(&game_id))
+  }
+
+  pub async fn Query_getGames(
+    &self,
+    _root: &Query,
+    ctx: &Context<'_>,
+    status: Option<Vec<GameStatus>>,
   ) -> GraphQLResult<Vec<Game>> {
-    Ok(
-      database::find_all_games()
-        .into_iter()
-        .map(|x| x.to_game())
-        .collect(),
-    )
+    let store = library_store(ctx);
+
+    store
+      .find_all_games()
+      .map_err(|err| err.extend())?
+      .into_iter()
+      .filter(|x| match &status {
+        Some(status) => status.contains(&x.resolve_status(store)),
+        None => true,
+      })
+      .map(|x| x.to_game(store))
+      .collect::<Result<_, _>>()
+      .map_err(|err| err.extend())
   }
 
   pub async fn Query_getKnownSourcePorts(
@@ -171,14 +456,17 @@ impl DataSource {
   pub async fn Query_getSourcePorts(
     &self,
     _root: &Query,
-    _ctx: &Context<'_>,
+    ctx: &Context<'_>,
   ) -> GraphQLResult<Vec<SourcePort>> {
-    Ok(
-      database::find_all_source_ports()
-        .into_iter()
-        .map(|x| x.to_source_port())
-        .collect(),
-    )
+    let store = library_store(ctx);
+
+    let db_source_ports = store.find_all_source_ports().map_err(|err| err.extend())?;
+
+    db_source_ports
+      .into_iter()
+      .map(|x| x.to_source_port())
+      .collect::<Result<_, _>>()
+      .map_err(|err| err.extend())
   }
 
   pub async fn Query_getAppInfo(&self, _root: &Query, ctx: &Context<'_>) -> GraphQLResult<AppInfo> {
@@ -194,26 +482,22 @@ impl DataSource {
   pub async fn Mutation_startGame(
     &self,
     _root: &Mutation,
-    _ctx: &Context<'_>,
+    ctx: &Context<'_>,
     game_id: String,
   ) -> GraphQLResult<bool> {
+    let store = library_store(ctx);
+
     // Get game configuration from database
-    let game = database::find_game_by_id(&game_id).ok_or_else(|| Error {
-      message: format!("game {} not found", game_id),
-      source: None,
-      extensions: None,
-    })?;
+    let game = store
+      .find_game_by_id(&game_id)
+      .map_err(|err| err.extend())?;
 
     // Get source port configuration
     let db_source_port = if game.source_port.as_deref() == Some("-1") {
       // Find the default source port or fall back to first one
-      let all_source_ports = database::find_all_source_ports();
+      let all_source_ports = store.find_all_source_ports().map_err(|err| err.extend())?;
       if all_source_ports.is_empty() {
-        return Err(Error {
-          message: "No source ports configured".to_string(),
-          source: None,
-          extensions: None,
-        });
+        return Err(WadpunkError::InvalidInput("No source ports configured".to_string()).extend());
       }
       all_source_ports
         .clone()
@@ -222,16 +506,27 @@ impl DataSource {
         .unwrap_or_else(|| all_source_ports.into_iter().next().unwrap())
     } else {
       // Use the specified source port
-      let source_port = game.source_port.ok_or_else(|| Error {
-        message: format!("game {} has no source port configured", game_id),
-        source: None,
-        extensions: None,
+      let source_port = game.source_port.clone().ok_or_else(|| {
+        WadpunkError::InvalidInput(format!("game {} has no source port configured", game_id))
+          .extend()
       })?;
-      database::find_source_port_by_id(&source_port)
+      store
+        .find_source_port_by_id(&source_port)
+        .map_err(|err| err.extend())?
     };
 
-    let db_source_port_command = db_source_port.command.unwrap();
-    let main_exe = db_source_port_command.first().unwrap().clone();
+    let db_source_port_command = db_source_port
+      .command
+      .clone()
+      .filter(|x| !x.is_empty())
+      .ok_or_else(|| {
+        WadpunkError::InvalidInput(format!(
+          "source port {} has no command",
+          db_source_port.id.clone().unwrap_or_default()
+        ))
+        .extend()
+      })?;
+    let main_exe = db_source_port_command[0].clone();
     let base_args = &db_source_port_command[1..];
 
     // If running on macOS, check if the `${source_port}/Contents/Info.plist`
@@ -242,12 +537,23 @@ impl DataSource {
       let plist_path = Path::new(&main_exe).join("Contents").join("Info.plist");
 
       if plist_path.exists() {
-        let info_plist = plist::Value::from_file(&plist_path).unwrap();
+        let info_plist = plist::Value::from_file(&plist_path).map_err(|err| WadpunkError::Parse {
+          path: plist_path.clone(),
+          message: err.to_string(),
+        });
         let executable = info_plist
-          .as_dictionary()
-          .and_then(|x| x.get("CFBundleExecutable"))
-          .and_then(|x| x.as_string())
-          .unwrap();
+          .and_then(|info_plist| {
+            info_plist
+              .as_dictionary()
+              .and_then(|x| x.get("CFBundleExecutable"))
+              .and_then(|x| x.as_string())
+              .map(|x| x.to_string())
+              .ok_or_else(|| WadpunkError::Parse {
+                path: plist_path.clone(),
+                message: "no CFBundleExecutable".to_string(),
+              })
+          })
+          .map_err(|err| err.extend())?;
 
         plist_path
           .parent()
@@ -264,28 +570,19 @@ impl DataSource {
       main_exe
     };
 
-    let source_port_definition = find_known_source_port_from_id(
-      &db_source_port
-        .known_source_port_id
-        .unwrap_or("gzdoom".to_string()),
-    );
+    let source_port_definition =
+      find_known_source_port_from_id(&db_source_port.known_source_port_id);
 
     // Check if this game is tagged as an IWAD
-    let is_game_iwad = game
-      .tags
-      .iter()
-      .flat_map(|tags| tags.iter())
-      .any(|tag| tag.to_lowercase() == "iwad");
+    let is_game_iwad = game.is_iwad();
 
     // Get the IWAD ID - if game is tagged as IWAD, use its own ID,
     // otherwise use configured IWAD
     let iwad_id = if is_game_iwad {
       game_id.clone()
     } else {
-      game.iwad_id.ok_or_else(|| Error {
-        message: format!("game {} has no IWAD configured", game_id),
-        source: None,
-        extensions: None,
+      game.iwad_id.ok_or_else(|| {
+        WadpunkError::InvalidInput(format!("game {} has no IWAD configured", game_id)).extend()
       })?
     };
 
@@ -306,10 +603,8 @@ impl DataSource {
     }
 
     // Ensure we found an IWAD
-    let iwad = iwad.ok_or_else(|| Error {
-      message: format!("game {} has no enabled IWAD files", game_id),
-      source: None,
-      extensions: None,
+    let iwad = iwad.ok_or_else(|| {
+      WadpunkError::InvalidInput(format!("game {} has no enabled IWAD files", game_id)).extend()
     })?;
 
     let use_custom_config = game.use_custom_config.unwrap_or_default();
@@ -329,13 +624,27 @@ impl DataSource {
     let mut play_session = DbPlaySessionEntry {
       started_at: Some(Utc::now().to_rfc3339()),
       ended_at: None,
+      source_port: db_source_port.id.clone(),
+      iwad_id: Some(iwad_id.clone()),
     };
 
-    let exit_status = command.status().unwrap();
+    let exit_status = command
+      .status()
+      .map_err(|err| WadpunkError::Io(err).extend())?;
 
     play_session.ended_at = Some(Utc::now().to_rfc3339());
 
-    database::record_game_play_session(&game_id, play_session);
+    store
+      .record_game_play_session(&game_id, play_session)
+      .map_err(|err| err.extend())?;
+
+    // A failed snapshot shouldn't make it look like the game itself failed.
+    if let Err(err) = saves::snapshot_game_saves(&game_id) {
+      library_issues::record_warning(
+        Some(&game_id),
+        format!("could not snapshot the saves: {}", err),
+      );
+    }
 
     Ok(exit_status.success())
   }
@@ -343,16 +652,15 @@ impl DataSource {
   pub async fn Mutation_openGamesFolder(
     &self,
     _root: &Mutation,
-    _ctx: &Context<'_>,
+    ctx: &Context<'_>,
     game_id: Option<String>,
   ) -> GraphQLResult<bool> {
-    let mut path_to_open = database::get_games_directory();
-
-    if let Some(game_id) = game_id {
-      path_to_open.push(game_id);
-    }
+    let path_to_open = match game_id {
+      Some(game_id) => library_store(ctx).get_game_path(&game_id),
+      None => database::get_games_directory(),
+    };
 
-    reveal_file_or_folder(path_to_open.to_str().unwrap());
+    reveal_file_or_folder(&path_to_open.to_string_lossy());
 
     Ok(true)
   }
@@ -363,7 +671,7 @@ impl DataSource {
     _ctx: &Context<'_>,
     _game_id: Option<String>,
   ) -> GraphQLResult<bool> {
-    reveal_file_or_folder(database::get_source_ports_directory().to_str().unwrap());
+    reveal_file_or_folder(&database::get_source_ports_directory().to_string_lossy());
 
     Ok(true)
   }
@@ -371,125 +679,190 @@ impl DataSource {
   pub async fn Mutation_updateNotes(
     &self,
     _root: &Mutation,
-    _ctx: &Context<'_>,
+    ctx: &Context<'_>,
     game_id: String,
     notes: String,
   ) -> GraphQLResult<Game> {
-    if let Some(mut db_game) = database::find_game_by_id(&game_id) {
-      db_game.notes = Some(notes);
+    let store = library_store(ctx);
 
-      database::save_game(db_game.clone());
+    let mut db_game = store
+      .find_game_by_id(&game_id)
+      .map_err(|err| err.extend())?;
 
-      return Ok(db_game.to_game());
-    }
+    db_game.notes = Some(notes);
 
-    Err(Error {
-      message: format!("game {} not found", game_id),
-      source: None,
-      extensions: None,
-    })
+    store
+      .save_game(db_game.clone())
+      .map_err(|err| err.extend())?;
+
+    db_game.to_game(store).map_err(|err| err.extend())
   }
 
   pub async fn Mutation_updateRating(
     &self,
     _root: &Mutation,
-    _ctx: &Context<'_>,
+    ctx: &Context<'_>,
     game_id: String,
     rating: i32,
   ) -> GraphQLResult<Game> {
-    if let Some(mut db_game) = database::find_game_by_id(&game_id) {
-      db_game.rating = Some(rating);
+    let store = library_store(ctx);
 
-      database::save_game(db_game.clone());
+    let mut db_game = store
+      .find_game_by_id(&game_id)
+      .map_err(|err| err.extend())?;
 
-      return Ok(db_game.to_game());
-    }
+    db_game.rating = Some(rating);
 
-    Err(Error {
-      message: format!("game {} not found", game_id),
-      source: None,
-      extensions: None,
-    })
+    store
+      .save_game(db_game.clone())
+      .map_err(|err| err.extend())?;
+
+    db_game.to_game(store).map_err(|err| err.extend())
   }
 
   pub async fn Mutation_updateTags(
     &self,
     _root: &Mutation,
-    _ctx: &Context<'_>,
+    ctx: &Context<'_>,
     game_id: String,
     tags: Vec<String>,
   ) -> GraphQLResult<Game> {
-    if let Some(mut db_game) = database::find_game_by_id(&game_id) {
-      db_game.tags = Some(tags);
+    let store = library_store(ctx);
 
-      database::save_game(db_game.clone());
+    let mut db_game = store
+      .find_game_by_id(&game_id)
+      .map_err(|err| err.extend())?;
 
-      return Ok(db_game.to_game());
-    }
+    db_game.tags = Some(tags);
 
-    Err(Error {
-      message: format!("game {} not found", game_id),
-      source: None,
-      extensions: None,
-    })
+    store
+      .save_game(db_game.clone())
+      .map_err(|err| err.extend())?;
+
+    db_game.to_game(store).map_err(|err| err.extend())
+  }
+
+  pub async fn Mutation_updateStatus(
+    &self,
+    _root: &Mutation,
+    ctx: &Context<'_>,
+    game_id: String,
+    status: Option<GameStatus>,
+  ) -> GraphQLResult<Game> {
+    let store = library_store(ctx);
+
+    let mut db_game = store
+      .find_game_by_id(&game_id)
+      .map_err(|err| err.extend())?;
+
+    db_game.status = status;
+
+    store
+      .save_game(db_game.clone())
+      .map_err(|err| err.extend())?;
+
+    db_game.to_game(store).map_err(|err| err.extend())
+  }
+
+  pub async fn Mutation_restoreSaveSnapshot(
+    &self,
+    _root: &Mutation,
+    ctx: &Context<'_>,
+    game_id: String,
+    snapshot_id: String,
+  ) -> GraphQLResult<Game> {
+    let store = library_store(ctx);
+
+    let db_game = store
+      .find_game_by_id(&game_id)
+      .map_err(|err| err.extend())?;
+
+    saves::restore_save_snapshot(&game_id, &snapshot_id).map_err(|err| err.extend())?;
+
+    db_game.to_game(store).map_err(|err| err.extend())
+  }
+
+  pub async fn Mutation_importSourcePortSaves(
+    &self,
+    _root: &Mutation,
+    ctx: &Context<'_>,
+    game_id: String,
+  ) -> GraphQLResult<Vec<SaveGame>> {
+    let store = library_store(ctx);
+
+    saves::import_source_port_saves(store, &game_id).map_err(|err| err.extend())
   }
 
   pub async fn Mutation_updateGame(
     &self,
     _root: &Mutation,
-    _ctx: &Context<'_>,
+    ctx: &Context<'_>,
     game: GameInput,
   ) -> GraphQLResult<Game> {
-    if let Some(mut db_game) = database::find_game_by_id(&game.id) {
-      if let Some(rating) = game.rating {
-        db_game.rating = Some(rating);
-      }
-      if let Some(description) = game.description {
-        db_game.description = Some(description);
-      }
-      if let Some(notes) = game.notes {
-        db_game.notes = Some(notes);
-      }
-      if let Some(tags) = game.tags {
-        db_game.tags = Some(tags);
-      }
+    let store = library_store(ctx);
 
-      if let Some(source_port) = game.source_port {
-        db_game.source_port = Some(source_port);
-      }
-      if let Some(iwad_id) = game.iwad_id {
-        db_game.iwad_id = Some(iwad_id);
-      }
-      if let Some(extra_mod_ids) = game.extra_mod_ids {
-        db_game.extra_mod_ids = Some(extra_mod_ids);
-      }
+    let mut db_game = store
+      .find_game_by_id(&game.id)
+      .map_err(|err| err.extend())?;
 
-      if let Some(use_custom_config) = game.use_custom_config {
-        db_game.use_custom_config = Some(use_custom_config);
-      }
-      if let Some(previous_file_state) = game.previous_file_state {
-        db_game.previous_file_state = Some(
-          previous_file_state
-            .into_iter()
-            .map(|x| DbPreviousFileStateItem {
-              is_enabled: x.is_enabled,
-              relative: x.relative,
-              absolute: x.absolute,
-            })
-            .collect(),
-        );
-      }
+    if let Some(rating) = game.rating {
+      db_game.rating = Some(rating);
+    }
+    if let Some(description) = game.description {
+      db_game.description = Some(description);
+    }
+    if let Some(notes) = game.notes {
+      db_game.notes = Some(notes);
+    }
+    if let Some(tags) = game.tags {
+      db_game.tags = Some(tags);
+    }
 
-      database::save_game(db_game.clone());
+    if let Some(author) = game.author {
+      db_game.author = Some(author);
+    }
+    if let Some(release_date) = game.release_date {
+      db_game.release_date = Some(release_date);
+    }
+    if let Some(requires_iwad) = game.requires_iwad {
+      db_game.requires_iwad = Some(requires_iwad);
+    }
+
+    if let Some(source_port) = game.source_port {
+      db_game.source_port = Some(source_port);
+    }
+    if let Some(iwad_id) = game.iwad_id {
+      db_game.iwad_id = Some(iwad_id);
+      db_game.iwad_id_inferred = None;
+    }
+    if let Some(extra_mod_ids) = game.extra_mod_ids {
+      db_game.extra_mod_ids = Some(extra_mod_ids);
+    }
 
-      return Ok(db_game.to_game());
+    if let Some(use_custom_config) = game.use_custom_config {
+      db_game.use_custom_config = Some(use_custom_config);
+    }
+    if let Some(status) = game.status {
+      db_game.status = Some(status);
+    }
+    if let Some(previous_file_state) = game.previous_file_state {
+      db_game.previous_file_state = Some(
+        previous_file_state
+          .into_iter()
+          .map(|x| DbPreviousFileStateItem {
+            is_enabled: x.is_enabled,
+            relative: x.relative,
+            absolute: x.absolute,
+          })
+          .collect(),
+      );
     }
 
-    Err(Error {
-      message: format!("game {} not found", game.id),
-      source: None,
-      extensions: None,
-    })
+    store
+      .save_game(db_game.clone())
+      .map_err(|err| err.extend())?;
+
+    db_game.to_game(store).map_err(|err| err.extend())
   }
 
   pub async fn Mutation_initializeApp(
@@ -497,7 +870,7 @@ impl DataSource {
     _root: &Mutation,
     _ctx: &Context<'_>,
   ) -> GraphQLResult<bool> {
-    database::init_games();
+    database::init_games().map_err(|err| err.extend())?;
 
     Ok(true)
   }
@@ -505,47 +878,60 @@ impl DataSource {
   pub async fn Mutation_createSourcePort(
     &self,
     _root: &Mutation,
-    _ctx: &Context<'_>,
+    ctx: &Context<'_>,
     source_port: CreateSourcePortInput,
   ) -> GraphQLResult<SourcePort> {
+    let store = library_store(ctx);
+
     let db_source_port = DbSourcePort {
+      schema_version: migrations::SOURCE_PORT_SCHEMA_VERSION,
       id: Some(source_port.id.clone()),
       command: Some(source_port.command),
-      known_source_port_id: Some(source_port.known_source_port_id),
+      known_source_port_id: source_port.known_source_port_id,
       is_default: source_port.is_default,
     };
 
-    database::save_source_port(db_source_port.clone());
-    database::set_default_source_port(&source_port.id, source_port.is_default);
+    store
+      .save_source_port(db_source_port.clone())
+      .and_then(|_| store.set_default_source_port(&source_port.id, source_port.is_default))
+      .map_err(|err| err.extend())?;
 
-    Ok(db_source_port.to_source_port())
+    db_source_port.to_source_port().map_err(|err| err.extend())
   }
 
   pub async fn Mutation_updateSourcePort(
     &self,
     _root: &Mutation,
-    _ctx: &Context<'_>,
+    ctx: &Context<'_>,
     source_port: UpdateSourcePortInput,
   ) -> GraphQLResult<SourcePort> {
-    let mut db_source_port = database::find_source_port_by_id(&source_port.id);
+    let store = library_store(ctx);
+
+    let mut db_source_port = store
+      .find_source_port_by_id(&source_port.id)
+      .map_err(|err| err.extend())?;
 
     db_source_port.command = Some(source_port.command);
-    db_source_port.known_source_port_id = Some(source_port.known_source_port_id);
+    db_source_port.known_source_port_id = source_port.known_source_port_id;
     db_source_port.is_default = source_port.is_default;
 
-    database::save_source_port(db_source_port.clone());
-    database::set_default_source_port(&source_port.id, source_port.is_default);
+    store
+      .save_source_port(db_source_port.clone())
+      .and_then(|_| store.set_default_source_port(&source_port.id, source_port.is_default))
+      .map_err(|err| err.extend())?;
 
-    Ok(db_source_port.to_source_port())
+    db_source_port.to_source_port().map_err(|err| err.extend())
   }
 
   pub async fn Mutation_deleteSourcePort(
     &self,
     _root: &Mutation,
-    _ctx: &Context<'_>,
+    ctx: &Context<'_>,
     id: String,
   ) -> GraphQLResult<bool> {
-    database::delete_source_port(&id);
+    let store = library_store(ctx);
+
+    store.delete_source_port(&id).map_err(|err| err.extend())?;
 
     Ok(true)
   }
@@ -555,22 +941,297 @@ impl DataSource {
     _root: &Mutation,
     ctx: &Context<'_>,
     file_path: String,
-  ) -> GraphQLResult<bool> {
+    conflict_policy: Option<ImportConflictPolicy>,
+    keep_archive: Option<bool>,
+  ) -> GraphQLResult<ImportResult> {
+    let store = library_store(ctx);
     let app_handle = ctx.data_unchecked::<AppHandle>();
 
-    let seven_zip_path = app_handle
-      .path_resolver()
-      .resolve_resource(add_exe_on_windows("resources-arch-specific/7za"))
-      .unwrap();
+    let seven_zip_path = resolve_seven_zip_path(app_handle).map_err(|err| err.extend())?;
 
-    let seven_zip_path_str = seven_zip_path.to_str().unwrap();
+    importer::import_file(
+      store,
+      &file_path,
+      &seven_zip_path,
+      conflict_policy.unwrap_or(ImportConflictPolicy::KEEP_BOTH),
+      keep_archive.unwrap_or_default(),
+    )
+    .map_err(|err| err.extend())
+  }
 
-    importer::import_file(&file_path, seven_zip_path_str);
+  pub async fn Mutation_referenceExternalGame(
+    &self,
+    _root: &Mutation,
+    ctx: &Context<'_>,
+    path: String,
+    conflict_policy: Option<ImportConflictPolicy>,
+  ) -> GraphQLResult<ImportResult> {
+    let store = library_store(ctx);
+
+    importer::reference_file(
+      store,
+      &path,
+      conflict_policy.unwrap_or(ImportConflictPolicy::KEEP_BOTH),
+    )
+    .map_err(|err| err.extend())
+  }
 
-    Ok(true)
+  pub async fn Mutation_registerInstalledIwads(
+    &self,
+    _root: &Mutation,
+    ctx: &Context<'_>,
+    paths: Vec<String>,
+    reference: Option<bool>,
+    conflict_policy: Option<ImportConflictPolicy>,
+  ) -> GraphQLResult<ImportSummary> {
+    let store = shared_library_store(ctx);
+    let app_handle = ctx.data_unchecked::<AppHandle>();
+
+    let seven_zip_path = resolve_seven_zip_path(app_handle).map_err(|err| err.extend())?;
+
+    tauri::async_runtime::spawn_blocking(move || {
+      store_installs::register_iwads(
+        store.as_ref(),
+        &paths,
+        reference.unwrap_or_default(),
+        &seven_zip_path,
+        conflict_policy.unwrap_or(ImportConflictPolicy::KEEP_BOTH),
+      )
+    })
+    .await
+    .map_err(to_join_graphql_error)
+  }
+
+  pub async fn Mutation_importFiles(
+    &self,
+    _root: &Mutation,
+    ctx: &Context<'_>,
+    file_paths: Vec<String>,
+    recursive: Option<bool>,
+    conflict_policy: Option<ImportConflictPolicy>,
+    keep_archive: Option<bool>,
+  ) -> GraphQLResult<ImportSummary> {
+    let store = shared_library_store(ctx);
+    let app_handle = ctx.data_unchecked::<AppHandle>().clone();
+
+    let seven_zip_path = resolve_seven_zip_path(&app_handle).map_err(|err| err.extend())?;
+
+    // Large batches take a while, keep them off the async runtime so the rest
+    // of the app stays responsive.
+    tauri::async_runtime::spawn_blocking(move || {
+      importer::import_files(
+        store.as_ref(),
+        &file_paths,
+        recursive.unwrap_or_default(),
+        &seven_zip_path,
+        conflict_policy.unwrap_or(ImportConflictPolicy::KEEP_BOTH),
+        keep_archive.unwrap_or_default(),
+        |progress| {
+          let _ = app_handle.emit_all("import-progress", progress);
+        },
+      )
+    })
+    .await
+    .map_err(to_join_graphql_error)
+  }
+
+  pub async fn Mutation_importFromUrl(
+    &self,
+    _root: &Mutation,
+    ctx: &Context<'_>,
+    url: String,
+    checksum: Option<String>,
+    conflict_policy: Option<ImportConflictPolicy>,
+    keep_archive: Option<bool>,
+  ) -> GraphQLResult<ImportResult> {
+    let store = shared_library_store(ctx);
+    let app_handle = ctx.data_unchecked::<AppHandle>().clone();
+
+    let seven_zip_path = resolve_seven_zip_path(&app_handle).map_err(|err| err.extend())?;
+
+    let url = downloader::resolve_url(&url, &downloader::get_mirror_base_url());
+
+    tauri::async_runtime::spawn_blocking(move || {
+      let download_path = downloader::download(&url, checksum.as_deref(), |progress| {
+        let _ = app_handle.emit_all("download-progress", progress);
+      })
+      .map_err(|err| err.extend())?;
+      let download_file = database::path_to_string(&download_path).map_err(|err| err.extend())?;
+
+      let import_result = importer::import_file(
+        store.as_ref(),
+        &download_file,
+        &seven_zip_path,
+        conflict_policy.unwrap_or(ImportConflictPolicy::KEEP_BOTH),
+        keep_archive.unwrap_or_default(),
+      )
+      .map_err(|err| err.extend())?;
+
+      // Failed imports keep the download around so they don't need to
+      // download it again.
+      if let Err(err) = downloader::remove_download(&download_path) {
+        library_issues::record_warning(
+          None,
+          format!("could not remove the download {:?}: {}", download_path, err),
+        );
+      }
+
+      Ok(import_result)
+    })
+    .await
+    .map_err(to_join_graphql_error)
+    .and_then(|x| x)
+  }
+
+  pub async fn Mutation_installIdgamesFile(
+    &self,
+    _root: &Mutation,
+    ctx: &Context<'_>,
+    id: String,
+    conflict_policy: Option<ImportConflictPolicy>,
+    keep_archive: Option<bool>,
+  ) -> GraphQLResult<ImportResult> {
+    let store = shared_library_store(ctx);
+    let app_handle = ctx.data_unchecked::<AppHandle>().clone();
+
+    let seven_zip_path = resolve_seven_zip_path(&app_handle).map_err(|err| err.extend())?;
+
+    let idgames_file = run_idgames_request(move || idgames::get_file(&id)).await?;
+
+    let url = downloader::resolve_url(
+      &idgames::get_mirror_path(&idgames_file),
+      &downloader::get_mirror_base_url(),
+    );
+
+    tauri::async_runtime::spawn_blocking(move || {
+      let download_path = downloader::download(&url, idgames_file.md5.as_deref(), |progress| {
+        let _ = app_handle.emit_all("download-progress", progress);
+      })
+      .map_err(|err| err.extend())?;
+      let download_file = database::path_to_string(&download_path).map_err(|err| err.extend())?;
+
+      let import_result = importer::import_file(
+        store.as_ref(),
+        &download_file,
+        &seven_zip_path,
+        conflict_policy.unwrap_or(ImportConflictPolicy::KEEP_BOTH),
+        keep_archive.unwrap_or_default(),
+      )
+      .map_err(|err| err.extend())?;
+
+      if let Some(game_id) = &import_result.game_id {
+        idgames::apply_idgames_file(store.as_ref(), game_id, &idgames_file)
+          .map_err(|err| err.extend())?;
+      }
+
+      if let Err(err) = downloader::remove_download(&download_path) {
+        library_issues::record_warning(
+          None,
+          format!("could not remove the download {:?}: {}", download_path, err),
+        );
+      }
+
+      Ok(import_result)
+    })
+    .await
+    .map_err(to_join_graphql_error)
+    .and_then(|x| x)
+  }
+
+  pub async fn Mutation_loadIdgamesIndex(
+    &self,
+    _root: &Mutation,
+    _ctx: &Context<'_>,
+    file_path: String,
+  ) -> GraphQLResult<i32> {
+    // Full listings are tens of megabytes.
+    let entry_count =
+      tauri::async_runtime::spawn_blocking(move || idgames_index::load_index(&file_path))
+        .await
+        .map_err(to_join_graphql_error)?
+        .map_err(|err| WadpunkError::from(err).extend())?;
+
+    Ok(entry_count.try_into().unwrap_or(i32::MAX))
+  }
+
+  pub async fn Mutation_matchIdgamesIndex(
+    &self,
+    _root: &Mutation,
+    ctx: &Context<'_>,
+  ) -> GraphQLResult<Vec<IdgamesIndexMatch>> {
+    let store = shared_library_store(ctx);
+
+    tauri::async_runtime::spawn_blocking(move || idgames_index::match_games(store.as_ref()))
+      .await
+      .map_err(to_join_graphql_error)?
+      .map_err(|err| err.extend())
+  }
+
+  pub async fn Mutation_updateAppSettings(
+    &self,
+    _root: &Mutation,
+    ctx: &Context<'_>,
+    settings: AppSettingsInput,
+  ) -> GraphQLResult<AppSettings> {
+    let app_handle = ctx.data_unchecked::<AppHandle>();
+
+    let mut db_app_settings = database::load_app_settings().map_err(|err| err.extend())?;
+
+    if let Some(mirror_base_url) = settings.mirror_base_url {
+      db_app_settings.mirror_base_url = Some(mirror_base_url);
+    }
+    if let Some(idgames_api_url) = settings.idgames_api_url {
+      db_app_settings.idgames_api_url = Some(idgames_api_url);
+    }
+
+    let are_inboxes_changed = settings.inbox_directories.is_some();
+    if let Some(inbox_directories) = settings.inbox_directories {
+      db_app_settings.inbox_directories = Some(inbox_directories);
+    }
+    if let Some(inbox_processed_directory) = settings.inbox_processed_directory {
+      db_app_settings.inbox_processed_directory = Some(inbox_processed_directory);
+    }
+
+    database::save_app_settings(db_app_settings.clone()).map_err(|err| err.extend())?;
+
+    if are_inboxes_changed {
+      inbox_watcher::start(app_handle.clone(), shared_library_store(ctx));
+    }
+
+    Ok(AppSettings {
+      dataDirectory: database::get_data_directory().to_string_lossy().to_string(),
+      mirror_base_url: downloader::get_mirror_base_url(),
+      idgames_api_url: idgames::get_idgames_api_url(),
+      inbox_directories: db_app_settings.inbox_directories.unwrap_or_default(),
+      inbox_processed_directory: db_app_settings
+        .inbox_processed_directory
+        .filter(|x| !x.is_empty()),
+    })
   }
 }
 
+/// idgames requests block, so they run off the async runtime.
+async fn run_idgames_request<T, F>(request: F) -> GraphQLResult<T>
+where
+  T: Send + 'static,
+  F: FnOnce() -> Result<T, idgames::IdgamesError> + Send + 'static,
+{
+  tauri::async_runtime::spawn_blocking(request)
+    .await
+    .map_err(to_join_graphql_error)?
+    .map_err(|err| err.extend())
+}
+
+/// The 7-Zip bundled with the app, which only a broken install is missing.
+pub fn resolve_seven_zip_path(app_handle: &AppHandle) -> Result<String, WadpunkError> {
+  let seven_zip_path = app_handle
+    .path_resolver()
+    .resolve_resource(add_exe_on_windows("resources-arch-specific/7za"))
+    .ok_or_else(|| WadpunkError::NotFound("the bundled 7-Zip".to_string()))?;
+
+  database::path_to_string(&seven_zip_path)
+}
+
 pub fn add_exe_on_windows(exe: &str) -> String {
   if cfg!(target_os = "windows") {
     format!("{}.exe", exe)
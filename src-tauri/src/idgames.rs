@@ -0,0 +1,251 @@
+use std::fmt;
+
+use async_graphql::Error;
+use async_graphql::ErrorExtensions;
+use serde_json::Value;
+
+use crate::database;
+use crate::error::to_graphql_error;
+use crate::error::WadpunkError;
+use crate::graphql::generated::IdgamesDirectory;
+use crate::graphql::generated::IdgamesFile;
+use crate::graphql::generated::IdgamesSearchType;
+use crate::library_store::LibraryStore;
+
+pub const DEFAULT_IDGAMES_API_URL: &str = "https://www.doomworld.com/idgames/api/api.php";
+
+#[derive(Debug)]
+pub enum IdgamesError {
+  Http(String),
+  Api(String),
+  InvalidResponse(String),
+}
+
+impl IdgamesError {
+  /// Sent to the frontend as `extensions.code`, like `WadpunkError::code`.
+  pub fn code(&self) -> &'static str {
+    match self {
+      Self::Http(_) => "NETWORK_ERROR",
+      Self::Api(_) | Self::InvalidResponse(_) => "IDGAMES_ERROR",
+    }
+  }
+}
+
+impl fmt::Display for IdgamesError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Http(message) => write!(f, "idgames request failed: {}", message),
+      Self::Api(message) => write!(f, "idgames: {}", message),
+      Self::InvalidResponse(message) => write!(f, "invalid idgames response: {}", message),
+    }
+  }
+}
+
+impl ErrorExtensions for IdgamesError {
+  fn extend(&self) -> Error {
+    to_graphql_error(self.to_string(), self.code())
+  }
+}
+
+impl From<ureq::Error> for IdgamesError {
+  fn from(err: ureq::Error) -> Self {
+    Self::Http(err.to_string())
+  }
+}
+
+impl From<std::io::Error> for IdgamesError {
+  fn from(err: std::io::Error) -> Self {
+    Self::Http(err.to_string())
+  }
+}
+
+pub fn get_idgames_api_url() -> String {
+  database::load_app_settings()
+    .unwrap_or_default()
+    .idgames_api_url
+    .filter(|x| !x.is_empty())
+    .unwrap_or(DEFAULT_IDGAMES_API_URL.to_string())
+}
+
+pub fn search(
+  query: &str,
+  search_type: IdgamesSearchType,
+) -> Result<Vec<IdgamesFile>, IdgamesError> {
+  let search_type = match search_type {
+    IdgamesSearchType::FILENAME => "filename",
+    IdgamesSearchType::TITLE => "title",
+    IdgamesSearchType::AUTHOR => "author",
+    IdgamesSearchType::EMAIL => "email",
+    IdgamesSearchType::DESCRIPTION => "description",
+    IdgamesSearchType::CREDITS => "credits",
+    IdgamesSearchType::EDITORS => "editors",
+    IdgamesSearchType::TEXTFILE => "textfile",
+  };
+
+  let content = request(&[
+    ("action", "search"),
+    ("query", query),
+    ("type", search_type),
+  ])?;
+
+  Ok(
+    list(&content["file"])
+      .into_iter()
+      .map(to_idgames_file)
+      .collect(),
+  )
+}
+
+/// Subdirectories of `name`, like `levels/doom2/`.
+pub fn get_directories(name: &str) -> Result<Vec<IdgamesDirectory>, IdgamesError> {
+  let content = request(&[("action", "getdirs"), ("name", name)])?;
+
+  Ok(
+    list(&content["dir"])
+      .into_iter()
+      .map(|x| IdgamesDirectory {
+        id: string_field(x, "id"),
+        name: string_field(x, "name"),
+      })
+      .collect(),
+  )
+}
+
+/// Files directly in `name`.
+pub fn get_files(name: &str) -> Result<Vec<IdgamesFile>, IdgamesError> {
+  let content = request(&[("action", "getfiles"), ("name", name)])?;
+
+  Ok(
+    list(&content["file"])
+      .into_iter()
+      .map(to_idgames_file)
+      .collect(),
+  )
+}
+
+/// A single file, including its text file.
+pub fn get_file(id: &str) -> Result<IdgamesFile, IdgamesError> {
+  let content = request(&[("action", "get"), ("id", id)])?;
+
+  Ok(to_idgames_file(&content))
+}
+
+/// Path of a file relative to the root of an idgames mirror.
+pub fn get_mirror_path(idgames_file: &IdgamesFile) -> String {
+  format!(
+    "{}/{}",
+    idgames_file.dir.trim_end_matches('/'),
+    idgames_file.filename
+  )
+}
+
+/// Keeps the idgames details on the game, and fills in whatever the import
+/// couldn't find out itself.
+pub fn apply_idgames_file(
+  store: &dyn LibraryStore,
+  game_id: &str,
+  idgames_file: &IdgamesFile,
+) -> Result<(), WadpunkError> {
+  let mut db_game = store.load_game_meta(game_id)?;
+
+  db_game.idgames_id = Some(idgames_file.id.clone());
+  db_game.idgames_rating = idgames_file.rating;
+  db_game.idgames_textfile = idgames_file.textfile.clone();
+
+  if db_game.name.as_deref() == Some(database::normalize_name_from_id(game_id))
+    && !idgames_file.title.is_empty()
+  {
+    db_game.name = Some(idgames_file.title.clone());
+  }
+
+  let fill = |value: &mut Option<String>, idgames_value: &str| {
+    if value.as_deref().unwrap_or_default().is_empty() && !idgames_value.is_empty() {
+      *value = Some(idgames_value.to_string());
+    }
+  };
+
+  fill(&mut db_game.author, &idgames_file.author);
+  fill(&mut db_game.description, &idgames_file.description);
+  fill(&mut db_game.release_date, &idgames_file.date);
+
+  store.save_game(db_game)
+}
+
+/// The API wraps results in `content`, and reports problems as `error` or
+/// `warning` instead of using status codes.
+fn request(query: &[(&str, &str)]) -> Result<Value, IdgamesError> {
+  let mut request = ureq::get(&get_idgames_api_url()).query("out", "json");
+  for (key, value) in query {
+    request = request.query(key, value);
+  }
+
+  let body = request.call()?.into_string()?;
+  let mut json: Value =
+    serde_json::from_str(&body).map_err(|err| IdgamesError::InvalidResponse(err.to_string()))?;
+
+  if let Some(error) = json.get("error") {
+    return Err(IdgamesError::Api(string_field(error, "message")));
+  }
+
+  // "No results" comes back as a warning without content.
+  Ok(
+    json
+      .get_mut("content")
+      .map(Value::take)
+      .unwrap_or(Value::Null),
+  )
+}
+
+/// A single result comes back as an object rather than a list of one.
+pub fn list(value: &Value) -> Vec<&Value> {
+  match value {
+    Value::Array(values) => values.iter().collect(),
+    Value::Null => vec![],
+    value => vec![value],
+  }
+}
+
+/// Numbers sometimes come back as strings, and the other way around.
+pub fn string_field(value: &Value, key: &str) -> String {
+  match &value[key] {
+    Value::String(x) => x.clone(),
+    Value::Number(x) => x.to_string(),
+    _ => String::new(),
+  }
+}
+
+pub fn number_field(value: &Value, key: &str) -> Option<f64> {
+  match &value[key] {
+    Value::Number(x) => x.as_f64(),
+    Value::String(x) => x.parse().ok(),
+    _ => None,
+  }
+}
+
+fn to_idgames_file(value: &Value) -> IdgamesFile {
+  let textfile = string_field(value, "textfile");
+  let md5 = string_field(value, "md5");
+
+  IdgamesFile {
+    id: string_field(value, "id"),
+    title: string_field(value, "title"),
+    dir: string_field(value, "dir"),
+    filename: string_field(value, "filename"),
+    size: number_field(value, "size")
+      .map(|x| x as i32)
+      .unwrap_or_default(),
+    date: string_field(value, "date"),
+    author: string_field(value, "author"),
+    description: string_field(value, "description"),
+    rating: number_field(value, "rating").map(|x| x as f32),
+    votes: number_field(value, "votes")
+      .map(|x| x as i32)
+      .unwrap_or_default(),
+    textfile: if textfile.is_empty() {
+      None
+    } else {
+      Some(textfile)
+    },
+    md5: if md5.is_empty() { None } else { Some(md5) },
+  }
+}
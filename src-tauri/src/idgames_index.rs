@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::database;
+use crate::error::WadpunkError;
+use crate::graphql::generated::IdgamesIndexMatch;
+use crate::graphql::generated::IdgamesMatchKind;
+use crate::idgames;
+use crate::library_store::LibraryStore;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct DbIdgamesIndexEntry {
+  // Relative to the root of the archive, like `levels/doom2/a-c/av.zip`.
+  pub path: String,
+  pub size: Option<u64>,
+  pub md5: Option<String>,
+  pub id: Option<String>,
+  pub title: Option<String>,
+  pub author: Option<String>,
+  pub date: Option<String>,
+}
+
+impl DbIdgamesIndexEntry {
+  fn file_stem(&self) -> Option<String> {
+    Path::new(&self.path)
+      .file_stem()
+      .and_then(|x| x.to_str())
+      .map(|x| x.to_lowercase())
+  }
+
+  /// Listings only have a year, and only for files older than six months.
+  fn release_year(&self) -> Option<String> {
+    let date = self.date.as_deref()?;
+    let year = date
+      .split(|x: char| !x.is_ascii_digit())
+      .find(|x| x.len() == 4)?;
+
+    Some(year.to_string())
+  }
+}
+
+/// Reads an `ls-laR` listing or a JSON export, and keeps it as the index.
+/// Returns how many files are in it.
+pub fn load_index(file_path: &str) -> io::Result<usize> {
+  let contents = fs::read(file_path)?;
+  let text = String::from_utf8_lossy(&contents);
+  let trimmed = text.trim_start();
+
+  let entries = if trimmed.starts_with('[') || trimmed.starts_with('{') {
+    let json: Value = serde_json::from_str(trimmed)
+      .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    parse_json_index(&json)
+  } else {
+    parse_ls_lr(&text)
+  };
+
+  let json_str = serde_json::to_string(&entries).unwrap();
+  fs::write(database::get_idgames_index_path(), json_str)?;
+
+  Ok(entries.len())
+}
+
+pub fn load_index_entries() -> Vec<DbIdgamesIndexEntry> {
+  let json_contents =
+    fs::read_to_string(database::get_idgames_index_path()).unwrap_or("[]".to_string());
+
+  serde_json::from_str::<Vec<DbIdgamesIndexEntry>>(&json_contents).unwrap_or_default()
+}
+
+/// Finds every game's idgames entry, and fills in what the game doesn't
+/// already have. A matching MD5 wins over a matching size, which wins over
+/// only the name matching.
+pub fn match_games(store: &dyn LibraryStore) -> Result<Vec<IdgamesIndexMatch>, WadpunkError> {
+  let entries = load_index_entries();
+  if entries.is_empty() {
+    return Ok(vec![]);
+  }
+
+  let mut entries_by_size: HashMap<u64, Vec<&DbIdgamesIndexEntry>> = HashMap::new();
+  let mut entries_by_stem: HashMap<String, Vec<&DbIdgamesIndexEntry>> = HashMap::new();
+  for entry in &entries {
+    if let Some(size) = entry.size {
+      entries_by_size.entry(size).or_default().push(entry);
+    }
+    if let Some(stem) = entry.file_stem() {
+      entries_by_stem.entry(stem).or_default().push(entry);
+    }
+  }
+
+  let mut matches: Vec<IdgamesIndexMatch> = vec![];
+
+  for db_game in store.find_all_games()? {
+    let game_id = db_game.id.clone().unwrap();
+    let game_stem = game_stem(&game_id);
+
+    let mut best: Option<(&DbIdgamesIndexEntry, IdgamesMatchKind)> = None;
+
+    // Games imported before their source was recorded only have their own
+    // files to go by, which match when they're the archive itself. The MD5 of
+    // those is only worked out when the size matches.
+    let sources: Vec<(u64, Option<String>, Option<String>)> = match db_game.source_size {
+      Some(size) => vec![(size, db_game.source_md5.clone(), None)],
+      None => store
+        .find_all_game_files(&game_id)
+        .into_iter()
+        .map(|file| file.path)
+        .filter_map(|file| Some((fs::metadata(&file).ok()?.len(), None, Some(file))))
+        .collect(),
+    };
+
+    for (size, md5, file) in sources {
+      let Some(same_size) = entries_by_size.get(&size) else {
+        continue;
+      };
+
+      // Hashing is slow, only bother when the size already matches.
+      let md5 = md5.or_else(|| file_md5(Path::new(file.as_deref()?)).ok());
+      if let Some(entry) = same_size.iter().find(|x| x.md5.is_some() && x.md5 == md5) {
+        best = Some((entry, IdgamesMatchKind::HASH));
+        break;
+      }
+
+      if best.is_none() {
+        if let Some(entry) = same_size
+          .iter()
+          .find(|x| x.file_stem().as_deref() == Some(game_stem.as_str()))
+        {
+          best = Some((entry, IdgamesMatchKind::SIZE));
+        }
+      }
+    }
+
+    // Names are only trusted when they're unique in the whole archive.
+    if best.is_none() {
+      if let Some([entry]) = entries_by_stem.get(&game_stem).map(|x| x.as_slice()) {
+        best = Some((entry, IdgamesMatchKind::NAME));
+      }
+    }
+
+    let Some((entry, matched_by)) = best else {
+      continue;
+    };
+
+    apply_index_entry(store, &game_id, entry)?;
+
+    matches.push(IdgamesIndexMatch {
+      game_id,
+      path: entry.path.clone(),
+      matched_by,
+    });
+  }
+
+  Ok(matches)
+}
+
+fn apply_index_entry(
+  store: &dyn LibraryStore,
+  game_id: &str,
+  entry: &DbIdgamesIndexEntry,
+) -> Result<(), WadpunkError> {
+  let mut db_game = store.load_game_meta(game_id)?;
+
+  db_game.idgames_path = Some(entry.path.clone());
+  if entry.id.is_some() {
+    db_game.idgames_id = entry.id.clone();
+  }
+
+  if db_game.name.as_deref() == Some(database::normalize_name_from_id(game_id)) {
+    if let Some(title) = entry.title.clone().filter(|x| !x.is_empty()) {
+      db_game.name = Some(title);
+    }
+  }
+  if db_game.author.as_deref().unwrap_or_default().is_empty() {
+    db_game.author = entry.author.clone().filter(|x| !x.is_empty());
+  }
+  if db_game
+    .release_date
+    .as_deref()
+    .unwrap_or_default()
+    .is_empty()
+  {
+    db_game.release_date = entry.release_year();
+  }
+
+  store.save_game(db_game)
+}
+
+/// `mymap (2)/` and `MyMap.wad` are both `mymap`.
+fn game_stem(game_id: &str) -> String {
+  let name = database::normalize_name_from_id(game_id);
+  let name = if game_id.ends_with('/') {
+    name
+  } else {
+    Path::new(name)
+      .file_stem()
+      .and_then(|x| x.to_str())
+      .unwrap_or(name)
+  };
+
+  let name = match name.rsplit_once(" (") {
+    Some((stem, suffix)) if suffix.ends_with(')') => stem,
+    _ => name,
+  };
+
+  name.to_lowercase()
+}
+
+pub fn file_md5(path: &Path) -> io::Result<String> {
+  let mut context = md5::Context::new();
+  io::copy(&mut File::open(path)?, &mut context)?;
+
+  Ok(format!("{:x}", context.compute()))
+}
+
+/// Exports are either a list of files, or wrapped the same way API responses
+/// are.
+fn parse_json_index(json: &Value) -> Vec<DbIdgamesIndexEntry> {
+  let files = match json {
+    Value::Array(_) => json,
+    _ => [&json["files"], &json["content"]["file"], &json["file"]]
+      .into_iter()
+      .find(|x| !x.is_null())
+      .unwrap_or(&Value::Null),
+  };
+
+  idgames::list(files)
+    .into_iter()
+    .filter_map(|x| {
+      let dir = idgames::string_field(x, "dir");
+      let filename = idgames::string_field(x, "filename");
+      let path = if filename.is_empty() {
+        idgames::string_field(x, "path")
+      } else {
+        format!("{}/{}", dir.trim_end_matches('/'), filename)
+      };
+
+      if path.is_empty() {
+        return None;
+      }
+
+      let optional_field =
+        |key: &str| Some(idgames::string_field(x, key)).filter(|x| !x.is_empty());
+
+      Some(DbIdgamesIndexEntry {
+        path: path.trim_start_matches('/').to_string(),
+        size: idgames::number_field(x, "size").map(|x| x as u64),
+        md5: optional_field("md5").map(|x| x.to_lowercase()),
+        id: optional_field("id"),
+        title: optional_field("title"),
+        author: optional_field("author"),
+        date: optional_field("date"),
+      })
+    })
+    .collect()
+}
+
+/// ```text
+/// ./levels/doom2/a-c:
+/// -rw-r--r--   1 idgames  idgames   123456 Mar  4  2001 av.zip
+/// ```
+fn parse_ls_lr(text: &str) -> Vec<DbIdgamesIndexEntry> {
+  let mut entries = vec![];
+  let mut directory = String::new();
+
+  for line in text.lines() {
+    if let Some(heading) = line.strip_suffix(':') {
+      directory = heading
+        .trim_start_matches("./")
+        .trim_start_matches('.')
+        .trim_matches('/')
+        .to_string();
+      continue;
+    }
+
+    // Only regular files, not directories or links.
+    if !line.starts_with('-') {
+      continue;
+    }
+
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 9 {
+      continue;
+    }
+
+    let (month, day, year_or_time) = (fields[5], fields[6], fields[7]);
+    let file_name = fields[8..].join(" ");
+
+    // Recent files have a time instead of a year.
+    let date = if year_or_time.contains(':') {
+      format!("{} {}", month, day)
+    } else {
+      format!("{} {} {}", month, day, year_or_time)
+    };
+
+    entries.push(DbIdgamesIndexEntry {
+      path: if directory.is_empty() {
+        file_name
+      } else {
+        format!("{}/{}", directory, file_name)
+      },
+      size: fields[4].parse().ok(),
+      md5: None,
+      id: None,
+      title: None,
+      author: None,
+      date: Some(date),
+    });
+  }
+
+  entries
+}
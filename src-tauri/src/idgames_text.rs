@@ -0,0 +1,222 @@
+use std::fs;
+use std::path::Path;
+
+use chrono::NaiveDate;
+
+use crate::database;
+use crate::error::WadpunkError;
+use crate::library_store::LibraryStore;
+
+// Code page 437, bytes 0x80 - 0xFF. Most idgames text files were written on
+// DOS, and the lower half is plain ASCII.
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+// Keys longer than this are more likely a sentence with a colon in it.
+const MAX_KEY_LENGTH: usize = 32;
+
+#[derive(Debug, Default)]
+pub struct IdgamesText {
+  pub title: Option<String>,
+  pub author: Option<String>,
+  pub release_date: Option<String>,
+  pub description: Option<String>,
+  pub requires_iwad: Option<String>,
+}
+
+impl IdgamesText {
+  fn is_empty(&self) -> bool {
+    self.title.is_none() && self.author.is_none() && self.description.is_none()
+  }
+}
+
+/// Text files are UTF-8 when they can be, and CP437 otherwise.
+pub fn decode_text(bytes: &[u8]) -> String {
+  if let Ok(text) = std::str::from_utf8(bytes) {
+    return text.to_string();
+  }
+
+  let high: Vec<char> = CP437_HIGH.chars().collect();
+
+  bytes
+    .iter()
+    .map(|&x| {
+      if x < 0x80 {
+        x as char
+      } else {
+        high[(x - 0x80) as usize]
+      }
+    })
+    .collect()
+}
+
+/// Parses the "Key : Value" template idgames uploads follow. Values can wrap
+/// onto following indented lines.
+pub fn parse_idgames_text(text: &str) -> IdgamesText {
+  let mut fields: Vec<(String, String)> = vec![];
+  let mut is_continuing = false;
+
+  for line in text.lines() {
+    let trimmed = line.trim();
+
+    // Blank lines and "===" / "* Section *" headers end the current value.
+    if trimmed.is_empty() || trimmed.starts_with('=') || trimmed.starts_with('*') {
+      is_continuing = false;
+      continue;
+    }
+
+    let starts_with_whitespace = line.starts_with(char::is_whitespace);
+
+    if let (false, Some((key, value))) = (starts_with_whitespace, trimmed.split_once(':')) {
+      let key = key.trim();
+
+      if !key.is_empty() && key.len() <= MAX_KEY_LENGTH {
+        fields.push((key.to_lowercase(), value.trim().to_string()));
+        is_continuing = true;
+        continue;
+      }
+    }
+
+    if is_continuing {
+      if let Some((_, value)) = fields.last_mut() {
+        if !value.is_empty() {
+          value.push(' ');
+        }
+        value.push_str(trimmed);
+      }
+    }
+  }
+
+  let find_field = |keys: &[&str]| {
+    fields
+      .iter()
+      .find(|(key, value)| keys.contains(&key.as_str()) && !value.is_empty())
+      .map(|(_, value)| value.clone())
+  };
+
+  IdgamesText {
+    title: find_field(&["title"]),
+    author: find_field(&["author", "authors", "author(s)"]),
+    release_date: find_field(&["release date", "date finished", "date"])
+      .map(|x| normalize_date(&x)),
+    description: find_field(&["description"]),
+    requires_iwad: find_field(&["game", "game(s)", "iwad"]).and_then(|x| iwad_from_game(&x)),
+  }
+}
+
+pub fn is_text_file(path: &Path) -> bool {
+  path
+    .extension()
+    .and_then(|x| x.to_str())
+    .map(|x| x.eq_ignore_ascii_case("txt"))
+    .unwrap_or_default()
+}
+
+/// Looks through an import's files for an idgames text file, preferring one
+/// named after the game.
+pub fn find_idgames_text(game_id: &str, files: &[String]) -> Option<IdgamesText> {
+  let text_files = files
+    .iter()
+    .filter(|x| is_text_file(Path::new(x)))
+    .filter_map(|x| Some((x.clone(), fs::read(x).ok()?)))
+    .collect();
+
+  find_idgames_text_in(game_id, text_files)
+}
+
+/// Same as `find_idgames_text`, for text files that were already read, like
+/// the ones in an archive that's kept as it is.
+pub fn find_idgames_text_in(
+  game_id: &str,
+  mut text_files: Vec<(String, Vec<u8>)>,
+) -> Option<IdgamesText> {
+  let name = database::normalize_name_from_id(game_id).to_lowercase();
+
+  text_files.sort_by_key(|(path, _)| {
+    Path::new(path)
+      .file_stem()
+      .and_then(|x| x.to_str())
+      .map(|x| x.to_lowercase() != name)
+      .unwrap_or(true)
+  });
+
+  text_files
+    .into_iter()
+    .map(|(_, contents)| parse_idgames_text(&decode_text(&contents)))
+    .find(|x| !x.is_empty())
+}
+
+/// Fills in whatever the game's meta doesn't already have from its text file.
+pub fn apply_idgames_text(
+  store: &dyn LibraryStore,
+  game_id: &str,
+  idgames_text: IdgamesText,
+) -> Result<(), WadpunkError> {
+  let mut db_game = store.load_game_meta(game_id)?;
+
+  // New games get named after their file, that doesn't count as a name.
+  if db_game.name.as_deref() == Some(database::normalize_name_from_id(game_id)) {
+    if let Some(title) = idgames_text.title {
+      db_game.name = Some(title);
+    }
+  }
+
+  if is_blank(&db_game.description) {
+    db_game.description = idgames_text.description;
+  }
+  if is_blank(&db_game.author) {
+    db_game.author = idgames_text.author;
+  }
+  if is_blank(&db_game.release_date) {
+    db_game.release_date = idgames_text.release_date;
+  }
+  if db_game.requires_iwad.is_none() {
+    db_game.requires_iwad = idgames_text.requires_iwad;
+  }
+
+  store.save_game(db_game)
+}
+
+fn is_blank(value: &Option<String>) -> bool {
+  value.as_deref().unwrap_or_default().is_empty()
+}
+
+/// Dates are free text, only the unambiguous formats are turned into
+/// `YYYY-MM-DD`.
+fn normalize_date(value: &str) -> String {
+  ["%Y/%m/%d", "%Y-%m-%d", "%Y.%m.%d", "%B %d, %Y", "%d %B %Y"]
+    .iter()
+    .find_map(|format| NaiveDate::parse_from_str(value, format).ok())
+    .map(|x| x.format("%Y-%m-%d").to_string())
+    .unwrap_or_else(|| value.to_string())
+}
+
+/// Maps the "Game" field to the IWAD it needs. Anything unrecognized is left
+/// out rather than guessed at.
+pub fn iwad_from_game(value: &str) -> Option<String> {
+  let value = value.to_lowercase();
+  let iwad = if value.contains("plutonia") {
+    "plutonia.wad"
+  } else if value.contains("tnt") || value.contains("evilution") {
+    "tnt.wad"
+  } else if value.contains("doom2")
+    || value.contains("doom 2")
+    || value.contains("doom ii")
+    || value.contains("final doom")
+  {
+    "doom2.wad"
+  } else if value.contains("heretic") {
+    "heretic.wad"
+  } else if value.contains("hexen") {
+    "hexen.wad"
+  } else if value.contains("strife") {
+    "strife1.wad"
+  } else if value.contains("chex") {
+    "chex.wad"
+  } else if value.contains("doom") {
+    "doom.wad"
+  } else {
+    return None;
+  };
+
+  Some(iwad.to_string())
+}
@@ -1,48 +1,1728 @@
-use std::{fs::create_dir_all, path::Path, process::Command};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::fs::create_dir_all;
+use std::fs::remove_dir;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
 
-use fs_extra::{copy_items, dir::CopyOptions};
+use async_graphql::Error;
+use async_graphql::ErrorExtensions;
+use fs_extra::dir::CopyOptions;
+use serde::Serialize;
+use tauri::api::dir::read_dir;
 
 use crate::database;
+use crate::database::DbPreviousFileStateItem;
+use crate::error::to_graphql_error;
+use crate::error::WadpunkError;
+use crate::file_types;
+use crate::file_types::FileType;
+use crate::graphql::generated::ImportConflictPolicy;
+use crate::graphql::generated::ImportResult;
+use crate::graphql::generated::ImportSummary;
+use crate::idgames_index;
+use crate::idgames_text;
+use crate::iwad_inference;
+use crate::known_source_ports::find_known_source_port_from_id;
+use crate::library_store::LibraryStore;
 
-pub fn import_file(file: &str, seven_zip_path: &str) {
-  let lowercase = file.to_lowercase();
+// Where tar puts its "ustar" magic, used to tell a compressed tarball from a
+// single compressed file.
+const TAR_MAGIC_OFFSET: usize = 257;
 
-  if lowercase.ends_with(".zip")
-    || lowercase.ends_with(".7z")
-    || lowercase.ends_with(".rar")
-    || lowercase.ends_with(".gz")
-    || lowercase.ends_with(".bz2")
-    || lowercase.ends_with(".xz")
-    || lowercase.ends_with(".zst")
-  {
-    extract_archive_to_games_directory(file, seven_zip_path);
-  } else {
-    let mut copy_options = CopyOptions::new();
-    copy_options.overwrite = true;
+// idgames text files are a few kilobytes, anything much bigger isn't one.
+const MAX_TEXT_FILE_SIZE: u64 = 1024 * 1024;
+
+// Files that go with a game rather than being one, imported on their own they
+// join the folder game they share a name with.
+const COMPANION_EXTENSIONS: [&str; 3] = ["deh", "bex", "txt"];
 
-    copy_items(&[file], database::get_games_directory(), &copy_options).unwrap();
+#[derive(Debug)]
+pub struct ExtractEntryError {
+  pub entry: String,
+  pub message: String,
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+  Io(io::Error),
+  Copy(fs_extra::error::Error),
+  SevenZip(String),
+  Entries(Vec<ExtractEntryError>),
+  Library(WadpunkError),
+}
+
+impl ImportError {
+  /// Same as `WadpunkError::code`, with one more for archives that couldn't
+  /// be extracted.
+  pub fn code(&self) -> &'static str {
+    match self {
+      Self::Io(_) | Self::Copy(_) => "IO_ERROR",
+      Self::SevenZip(_) | Self::Entries(_) => "EXTRACT_ERROR",
+      Self::Library(err) => err.code(),
+    }
   }
 }
 
-pub fn extract_archive_to_games_directory(file: &str, seven_zip_path: &str) {
-  let games_directory = database::get_games_directory();
+impl fmt::Display for ImportError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Io(err) => write!(f, "{}", err),
+      Self::Copy(err) => write!(f, "{}", err),
+      Self::SevenZip(output) => write!(f, "7za failed: {}", output),
+      Self::Entries(entry_errors) => {
+        write!(f, "could not extract {} entries", entry_errors.len())?;
+
+        for entry_error in entry_errors {
+          write!(f, "\n{}: {}", entry_error.entry, entry_error.message)?;
+        }
+
+        Ok(())
+      }
+      Self::Library(err) => write!(f, "{}", err),
+    }
+  }
+}
+
+impl From<io::Error> for ImportError {
+  fn from(err: io::Error) -> Self {
+    Self::Io(err)
+  }
+}
+
+impl From<WadpunkError> for ImportError {
+  fn from(err: WadpunkError) -> Self {
+    Self::Library(err)
+  }
+}
+
+impl ErrorExtensions for ImportError {
+  fn extend(&self) -> Error {
+    to_graphql_error(self.to_string(), self.code())
+  }
+}
+
+#[derive(Clone, Copy)]
+enum ArchiveKind {
+  Zip,
+  Tar,
+  Gzip,
+  Bzip2,
+  Xz,
+  Zstd,
+  // Anything only 7za knows how to read.
+  SevenZip,
+}
+
+impl ArchiveKind {
+  fn from_file_type(file_type: FileType) -> Option<Self> {
+    match file_type {
+      FileType::Zip => Some(Self::Zip),
+      FileType::Tar => Some(Self::Tar),
+      FileType::Gzip => Some(Self::Gzip),
+      FileType::Bzip2 => Some(Self::Bzip2),
+      FileType::Xz => Some(Self::Xz),
+      FileType::Zstd => Some(Self::Zstd),
+      FileType::SevenZip | FileType::Rar => Some(Self::SevenZip),
+      FileType::Iwad | FileType::Pwad | FileType::Unknown => None,
+    }
+  }
+}
+
+enum ImportAction {
+  CopyDirectory,
+  CopyFile,
+  // An archive the game's source port can load, copied instead of extracted.
+  KeepArchive(ArchiveKind),
+  Extract(ArchiveKind),
+}
+
+struct ImportTarget {
+  destination: PathBuf,
+  game_id: String,
+  // Set when overwriting. `destination` is then a staging path next to the
+  // game, and this is only replaced once the import worked.
+  replaces: Option<PathBuf>,
+}
+
+enum ImportItem {
+  Single(PathBuf),
+  // Loose files that share a stem, imported together as one folder game.
+  Group(String, Vec<PathBuf>),
+}
+
+impl ImportItem {
+  fn paths(&self) -> &[PathBuf] {
+    match self {
+      Self::Single(path) => std::slice::from_ref(path),
+      Self::Group(_, paths) => paths,
+    }
+  }
+
+  /// What progress is reported as, `folder/mymap` for a group.
+  fn display_path(&self) -> PathBuf {
+    match self {
+      Self::Single(path) => path.clone(),
+      Self::Group(name, paths) => paths[0].with_file_name(name),
+    }
+  }
+}
+
+/// With `keep_archive`, zip and 7z archives are kept as they are when the
+/// game's source port loads them directly, instead of being extracted.
+pub fn import_file(
+  store: &dyn LibraryStore,
+  file: &str,
+  seven_zip_path: &str,
+  conflict_policy: ImportConflictPolicy,
+  keep_archive: bool,
+) -> Result<ImportResult, ImportError> {
+  let path = Path::new(file);
+  let file_name = get_file_name(path)?;
+  let mut warnings: Vec<String> = vec![];
 
   // Get basename of source without extension.
   // Would prefer to use `.file_prefix`, but of course that's "experimental" in
   // Rust, so we have to use the potentially uglier `.file_stem` instead.
-  let basename = Path::new(file).file_stem().unwrap().to_str().unwrap();
+  let stem = path
+    .file_stem()
+    .and_then(|x| x.to_str())
+    .unwrap_or(file_name);
+  let basename = stem.strip_suffix(".tar").unwrap_or(stem);
+
+  // Folders, and archives that source ports load directly, are kept as they
+  // are. Otherwise extensions can't be trusted, old uploads are full of things
+  // like `.wad` files that are really zips.
+  let (action, target_name) = if path.is_dir() {
+    (ImportAction::CopyDirectory, file_name.to_string())
+  } else if file_types::is_loadable_archive(path) {
+    (ImportAction::CopyFile, file_name.to_string())
+  } else {
+    let file_type = file_types::detect_file_type(path)?;
+    let is_keepable = keep_archive && matches!(file_type, FileType::Zip | FileType::SevenZip);
+
+    if let Some(archive_kind) = ArchiveKind::from_file_type(file_type) {
+      if is_keepable && source_port_supports_archive(store, file_name, file_type)? {
+        (
+          ImportAction::KeepArchive(archive_kind),
+          file_name.to_string(),
+        )
+      } else {
+        if is_keepable {
+          warnings.push(format!(
+            "{} was extracted, the game's source port can't load it as it is",
+            file_name
+          ));
+        }
+
+        (ImportAction::Extract(archive_kind), basename.to_string())
+      }
+    } else if file_type.is_wad() && !file_types::has_wad_extension(path) {
+      // Source ports, and the file list, go by extension.
+      let target_name = format!("{}.wad", stem);
+      warnings.push(format!(
+        "{} is a WAD, imported as {}",
+        file_name, target_name
+      ));
+
+      (ImportAction::CopyFile, target_name)
+    } else {
+      (ImportAction::CopyFile, file_name.to_string())
+    }
+  };
+
+  // Companion files imported on their own, like a `.deh` for a game that's
+  // already in the library, join the folder game they share a name with.
+  if matches!(action, ImportAction::CopyFile) {
+    if let Some(game_id) = find_companion_game(&target_name, &mut warnings)? {
+      return import_companion_file(store, &game_id, path, &target_name, warnings);
+    }
+  }
+
+  let is_directory = !matches!(
+    action,
+    ImportAction::CopyFile | ImportAction::KeepArchive(_)
+  );
+  let kept_archive_kind = match action {
+    ImportAction::KeepArchive(archive_kind) => Some(archive_kind),
+    _ => None,
+  };
+
+  let Some(target) = resolve_import_target(
+    store,
+    &target_name,
+    is_directory,
+    conflict_policy,
+    &mut warnings,
+  )?
+  else {
+    return Ok(ImportResult {
+      game_id: None,
+      files: vec![],
+      warnings,
+      skipped: true,
+      joined_existing_game: false,
+    });
+  };
+
+  let destination = &target.destination;
+  let destination_existed = destination.exists();
+
+  let result = match action {
+    ImportAction::CopyDirectory => {
+      create_dir_all(destination)?;
+
+      let mut copy_options = CopyOptions::new();
+      copy_options.content_only = true;
+
+      fs_extra::dir::copy(path, destination, &copy_options)
+        .map(|_| ())
+        .map_err(ImportError::Copy)
+    }
+    ImportAction::CopyFile | ImportAction::KeepArchive(_) => {
+      if let Some(parent) = destination.parent() {
+        create_dir_all(parent)?;
+      }
+
+      fs::copy(path, destination)
+        .map(|_| ())
+        .map_err(ImportError::Io)
+    }
+    ImportAction::Extract(archive_kind) => {
+      create_dir_all(destination)?;
+
+      extract_archive(file, archive_kind, basename, destination, seven_zip_path)
+    }
+  };
+
+  let staged_files = list_imported_files(destination)?;
+
+  match result {
+    Ok(()) => {}
+    // Some entries failing is worth knowing about, but shouldn't throw away
+    // the ones that worked.
+    Err(ImportError::Entries(entry_errors)) if !staged_files.is_empty() => {
+      for entry_error in entry_errors {
+        warnings.push(format!(
+          "could not extract {}: {}",
+          entry_error.entry, entry_error.message
+        ));
+      }
+    }
+    Err(err) => {
+      if target.replaces.is_some() {
+        let _ = remove_path(destination);
+      } else if !destination_existed {
+        // Don't leave an empty game behind when nothing could be imported.
+        // This only succeeds if the directory is empty.
+        let _ = remove_dir(destination);
+      }
+
+      return Err(err);
+    }
+  }
+
+  let destination = commit_import_target(store, &target, &mut warnings)?;
+  let files = list_imported_files(&destination)?;
+
+  let mut db_game = store.load_game_meta(&target.game_id)?;
+  if path.is_file() {
+    db_game.source_md5 = Some(idgames_index::file_md5(path)?);
+    db_game.source_size = Some(fs::metadata(path)?.len());
+  }
+  if let Some(archive_kind) = kept_archive_kind {
+    db_game.keep_archive = Some(true);
+    db_game.archive_entries = match list_archive_entries(&destination, archive_kind, seven_zip_path)
+    {
+      Ok(entries) => Some(entries),
+      Err(err) => {
+        warnings.push(format!(
+          "could not list the files in {}: {}",
+          file_name, err
+        ));
+        None
+      }
+    };
+  }
+  store.save_game(db_game)?;
+
+  // Kept archives have their text file inside them.
+  let idgames_text = match kept_archive_kind {
+    Some(archive_kind) => {
+      match read_archive_text_files(&destination, archive_kind, seven_zip_path) {
+        Ok(text_files) => idgames_text::find_idgames_text_in(&target.game_id, text_files),
+        Err(err) => {
+          warnings.push(format!(
+            "could not read the text files in {}: {}",
+            file_name, err
+          ));
+          None
+        }
+      }
+    }
+    None => idgames_text::find_idgames_text(&target.game_id, &files),
+  };
+  if let Some(idgames_text) = idgames_text {
+    idgames_text::apply_idgames_text(store, &target.game_id, idgames_text)?;
+  }
+  apply_default_file_state(store, &target.game_id, &files)?;
+
+  Ok(ImportResult {
+    game_id: Some(target.game_id),
+    files,
+    warnings,
+    skipped: false,
+    joined_existing_game: false,
+  })
+}
+
+/// Imports loose files that belong together, like `mymap.wad`, `mymap.deh`
+/// and `mymap.txt`, as a single folder game called `name`.
+pub fn import_file_group(
+  store: &dyn LibraryStore,
+  name: &str,
+  files: &[PathBuf],
+  conflict_policy: ImportConflictPolicy,
+) -> Result<ImportResult, ImportError> {
+  let mut warnings: Vec<String> = vec![];
+
+  let Some(target) = resolve_import_target(store, name, true, conflict_policy, &mut warnings)?
+  else {
+    return Ok(ImportResult {
+      game_id: None,
+      files: vec![],
+      warnings,
+      skipped: true,
+      joined_existing_game: false,
+    });
+  };
+
+  create_dir_all(&target.destination)?;
+
+  for file in files {
+    let file_name = get_file_name(file)?;
+
+    if let Err(err) = fs::copy(file, target.destination.join(file_name)) {
+      if target.replaces.is_some() {
+        let _ = remove_path(&target.destination);
+      }
+
+      return Err(err.into());
+    }
+  }
+
+  let destination = commit_import_target(store, &target, &mut warnings)?;
+  let files = list_imported_files(&destination)?;
+
+  if let Some(idgames_text) = idgames_text::find_idgames_text(&target.game_id, &files) {
+    idgames_text::apply_idgames_text(store, &target.game_id, idgames_text)?;
+  }
+  apply_default_file_state(store, &target.game_id, &files)?;
+
+  Ok(ImportResult {
+    game_id: Some(target.game_id),
+    files,
+    warnings,
+    skipped: false,
+    joined_existing_game: false,
+  })
+}
+
+/// The folder game in the games directory named like the file, without the
+/// extension. Only patches and text files are companions, anything else is a
+/// game of its own. Files the folder already has aren't companions either,
+/// they go through the conflict policy like anything else. Single file games
+/// can't take a companion, that's only noted.
+fn find_companion_game(
+  target_name: &str,
+  warnings: &mut Vec<String>,
+) -> Result<Option<String>, WadpunkError> {
+  let path = Path::new(target_name);
+  let Some(stem) = path.file_stem().and_then(|x| x.to_str()) else {
+    return Ok(None);
+  };
+  let is_companion = path
+    .extension()
+    .and_then(|x| x.to_str())
+    .map(|x| COMPANION_EXTENSIONS.contains(&x.to_lowercase().as_str()))
+    .unwrap_or_default();
+  if !is_companion {
+    return Ok(None);
+  }
+
+  for entry in fs::read_dir(database::get_games_directory())? {
+    let entry = entry?;
+    let Some(name) = entry.file_name().to_str().map(|x| x.to_string()) else {
+      continue;
+    };
+
+    if name.starts_with('.') || name == target_name {
+      continue;
+    }
+
+    let name_stem = Path::new(&name)
+      .file_stem()
+      .and_then(|x| x.to_str())
+      .unwrap_or_default();
+
+    if entry.path().is_dir() && name.eq_ignore_ascii_case(stem) {
+      if entry.path().join(target_name).exists() {
+        return Ok(None);
+      }
+
+      return Ok(Some(format!("{}/", name)));
+    } else if !entry.path().is_dir() && name_stem.eq_ignore_ascii_case(stem) {
+      warnings.push(format!(
+        "{} is a game on its own, import them together to make one game",
+        name
+      ));
+    }
+  }
+
+  Ok(None)
+}
+
+fn import_companion_file(
+  store: &dyn LibraryStore,
+  game_id: &str,
+  path: &Path,
+  target_name: &str,
+  mut warnings: Vec<String>,
+) -> Result<ImportResult, ImportError> {
+  let destination = database::get_games_directory()
+    .join(database::normalize_name_from_id(game_id))
+    .join(target_name);
+
+  fs::copy(path, &destination)?;
+  warnings.push(format!(
+    "added to {}, which has the same name",
+    database::normalize_name_from_id(game_id)
+  ));
+
+  let files = vec![database::path_to_string(&destination)?];
+
+  if let Some(idgames_text) = idgames_text::find_idgames_text(game_id, &files) {
+    idgames_text::apply_idgames_text(store, game_id, idgames_text)?;
+  }
+
+  // Games that were set up already get the file added to what they load,
+  // enabled when it's something that gets loaded.
+  let mut db_game = store.load_game_meta(game_id)?;
+  match &mut db_game.previous_file_state {
+    Some(file_state) => {
+      file_state.extend(default_file_state(store, game_id, &files));
+      store.save_game(db_game)?;
+    }
+    None => {
+      let files: Vec<String> = store
+        .find_all_game_files(game_id)
+        .into_iter()
+        .map(|x| x.path)
+        .collect();
+      apply_default_file_state(store, game_id, &files)?
+    }
+  }
+
+  Ok(ImportResult {
+    game_id: Some(game_id.to_string()),
+    files,
+    warnings,
+    skipped: false,
+    joined_existing_game: true,
+  })
+}
+
+/// Adds a file or folder as a game where it is, without copying it into the
+/// games directory.
+pub fn reference_file(
+  store: &dyn LibraryStore,
+  file: &str,
+  conflict_policy: ImportConflictPolicy,
+) -> Result<ImportResult, ImportError> {
+  let path = fs::canonicalize(file)?;
+  let file_name = get_file_name(&path)?;
+  let is_directory = path.is_dir();
+  let mut warnings: Vec<String> = vec![];
+
+  // Versions are folders inside the game, and there's no writing into
+  // someone else's folder.
+  let conflict_policy = if conflict_policy == ImportConflictPolicy::MERGE_AS_NEW_VERSION {
+    warnings.push(format!(
+      "{} is referenced where it is and can't hold versions, kept both instead",
+      file_name
+    ));
+
+    ImportConflictPolicy::KEEP_BOTH
+  } else {
+    conflict_policy
+  };
+
+  let Some(target) = resolve_import_target(
+    store,
+    file_name,
+    is_directory,
+    conflict_policy,
+    &mut warnings,
+  )?
+  else {
+    return Ok(ImportResult {
+      game_id: None,
+      files: vec![],
+      warnings,
+      skipped: true,
+      joined_existing_game: false,
+    });
+  };
+
+  // Nothing is staged, this only clears what's being overwritten.
+  commit_import_target(store, &target, &mut warnings)?;
+
+  let mut db_game = store.load_game_meta(&target.game_id)?;
+  db_game.external_path = Some(database::path_to_string(&path)?);
+  store.save_game(db_game)?;
+
+  let files: Vec<String> = store
+    .find_all_game_files(&target.game_id)
+    .into_iter()
+    .map(|x| x.path)
+    .collect();
+
+  if let Some(idgames_text) = idgames_text::find_idgames_text(&target.game_id, &files) {
+    idgames_text::apply_idgames_text(store, &target.game_id, idgames_text)?;
+  }
+  apply_default_file_state(store, &target.game_id, &files)?;
+
+  Ok(ImportResult {
+    game_id: Some(target.game_id),
+    files,
+    warnings,
+    skipped: false,
+    joined_existing_game: false,
+  })
+}
+
+/// Sets up a freshly imported game so it can be started right away: its IWAD
+/// is picked from what the game says it needs, or what its maps suggest, and
+/// its files are enabled.
+/// Games that already have a file state, like ones that got a new version
+/// merged in, are left alone.
+fn apply_default_file_state(
+  store: &dyn LibraryStore,
+  game_id: &str,
+  files: &[String],
+) -> Result<(), WadpunkError> {
+  let mut db_game = store.load_game_meta(game_id)?;
+  if db_game.previous_file_state.is_some() {
+    return Ok(());
+  }
+
+  // An imported IWAD is its own IWAD.
+  let has_iwad_file = files.iter().any(|x| {
+    file_types::detect_file_type(Path::new(x))
+      .map(|x| x == FileType::Iwad)
+      .unwrap_or_default()
+  });
+  if db_game.tags.is_none() && has_iwad_file {
+    db_game.tags = Some(vec!["iwad".to_string()]);
+  }
+
+  // The IWAD's files come first, same as in the game dialog.
+  let mut file_state: Vec<DbPreviousFileStateItem> = vec![];
+  if !db_game.is_iwad() {
+    let required_iwads =
+      iwad_inference::infer_required_iwads(files, db_game.requires_iwad.as_deref());
+
+    if db_game.requires_iwad.is_none() {
+      db_game.requires_iwad = required_iwads.first().cloned();
+    }
+    if db_game.iwad_id.is_none() {
+      for required_iwad in &required_iwads {
+        db_game.iwad_id = find_iwad_game_id(store, required_iwad)?;
+
+        if db_game.iwad_id.is_some() {
+          db_game.iwad_id_inferred = Some(true);
+          break;
+        }
+      }
+    }
+
+    if let Some(iwad_id) = &db_game.iwad_id {
+      let iwad_files: Vec<String> = store
+        .find_all_game_files(iwad_id)
+        .into_iter()
+        .map(|x| x.path)
+        .collect();
+      file_state.extend(default_file_state(store, iwad_id, &iwad_files));
+    }
+  }
+  file_state.extend(default_file_state(store, game_id, files));
+
+  db_game.previous_file_state = Some(file_state);
+  store.save_game(db_game)
+}
+
+/// Finds the library's IWAD game for an IWAD file name like `doom2.wad`,
+/// either by the game's own name or by a file inside it.
+pub fn find_iwad_game_id(
+  store: &dyn LibraryStore,
+  iwad_file_name: &str,
+) -> Result<Option<String>, WadpunkError> {
+  let Some(iwad_stem) = Path::new(iwad_file_name)
+    .file_stem()
+    .and_then(|x| x.to_str())
+  else {
+    return Ok(None);
+  };
+
+  let iwad_game_id = store
+    .find_all_games()?
+    .into_iter()
+    .filter(|x| x.is_iwad())
+    .filter_map(|x| x.id)
+    .find(|game_id| {
+      Path::new(database::normalize_name_from_id(game_id))
+        .file_stem()
+        .and_then(|x| x.to_str())
+        .map(|x| x.eq_ignore_ascii_case(iwad_stem))
+        .unwrap_or_default()
+        || store.find_all_game_files(game_id).iter().any(|x| {
+          Path::new(&x.path)
+            .file_name()
+            .and_then(|x| x.to_str())
+            .map(|x| x.eq_ignore_ascii_case(iwad_file_name))
+            .unwrap_or_default()
+        })
+    });
+
+  Ok(iwad_game_id)
+}
+
+/// Every imported file, enabled if a source port can load it. Files are
+/// ordered the way they should be passed to the source port: WADs and
+/// archives first, then DeHackEd patches, then everything else.
+pub fn default_file_state(
+  store: &dyn LibraryStore,
+  game_id: &str,
+  files: &[String],
+) -> Vec<DbPreviousFileStateItem> {
+  let game_path = store.get_game_path(game_id);
+
+  let mut file_state: Vec<(Option<u8>, DbPreviousFileStateItem)> = files
+    .iter()
+    .map(|absolute| {
+      let load_order = file_load_order(Path::new(absolute));
+      let relative = database::get_relative_game_file_path(game_id, &game_path, absolute);
+
+      (
+        load_order,
+        DbPreviousFileStateItem {
+          is_enabled: load_order.is_some(),
+          relative,
+          absolute: absolute.clone(),
+        },
+      )
+    })
+    .collect();
+
+  file_state.sort_by(|(a_order, a), (b_order, b)| {
+    (a_order.unwrap_or(u8::MAX), &a.relative).cmp(&(b_order.unwrap_or(u8::MAX), &b.relative))
+  });
+
+  file_state.into_iter().map(|(_, x)| x).collect()
+}
+
+/// `None` for files source ports don't load, like text files and
+/// screenshots.
+fn file_load_order(path: &Path) -> Option<u8> {
+  let extension = path.extension()?.to_str()?.to_lowercase();
+
+  if file_types::has_wad_extension(path)
+    || file_types::is_loadable_archive(path)
+    || extension == "zip"
+    || extension == "7z"
+  {
+    Some(0)
+  } else if extension == "deh" || extension == "bex" {
+    Some(1)
+  } else {
+    None
+  }
+}
+
+/// Whether the source port the game launches with loads `file_type` archives
+/// as they are. That's the game's own source port when it's already in the
+/// library, the default one otherwise.
+fn source_port_supports_archive(
+  store: &dyn LibraryStore,
+  game_id: &str,
+  file_type: FileType,
+) -> Result<bool, WadpunkError> {
+  let source_port_id = if store.game_exists(game_id) {
+    store
+      .load_game_meta(game_id)?
+      .source_port
+      .filter(|x| x != "-1")
+  } else {
+    None
+  };
+
+  let db_source_port = match source_port_id {
+    Some(source_port_id) => store.find_source_port_by_id(&source_port_id).ok(),
+    None => {
+      let all_source_ports = store.find_all_source_ports()?;
+      let default_index = all_source_ports
+        .iter()
+        .position(|x| x.is_default.unwrap_or_default())
+        .unwrap_or_default();
+
+      all_source_ports.into_iter().nth(default_index)
+    }
+  };
+
+  Ok(db_source_port.is_some_and(|x| {
+    find_known_source_port_from_id(&x.known_source_port_id).supports_archive(file_type)
+  }))
+}
+
+/// The text files in an archive that's kept as it is, by their path in the
+/// archive.
+fn read_archive_text_files(
+  file: &Path,
+  archive_kind: ArchiveKind,
+  seven_zip_path: &str,
+) -> Result<Vec<(String, Vec<u8>)>, ImportError> {
+  if !matches!(archive_kind, ArchiveKind::SevenZip) {
+    return Ok(read_zip_text_files(file)?);
+  }
+
+  // 7-Zip can only extract them, into a folder next to the archive that's
+  // removed again.
+  let text_directory = file.with_file_name(format!(".{}.text", get_file_name(file)?));
+  let _ = remove_path(&text_directory);
+  create_dir_all(&text_directory)?;
+
+  let result = extract_with_seven_zip(
+    &database::path_to_string(file)?,
+    &text_directory,
+    seven_zip_path,
+    &["*.txt", "-r"],
+  )
+  .and_then(|_| {
+    let mut text_files = vec![];
+
+    for text_file in list_imported_files(&text_directory)? {
+      let text_path = Path::new(&text_file);
+      if fs::metadata(text_path)?.len() > MAX_TEXT_FILE_SIZE {
+        continue;
+      }
+
+      let relative = text_path.strip_prefix(&text_directory).unwrap_or(text_path);
+      text_files.push((relative.to_string_lossy().to_string(), fs::read(text_path)?));
+    }
+
+    Ok(text_files)
+  });
+
+  let _ = remove_path(&text_directory);
+
+  result
+}
+
+fn read_zip_text_files(path: &Path) -> io::Result<Vec<(String, Vec<u8>)>> {
+  let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+
+  let mut text_files = vec![];
+  for i in 0..archive.len() {
+    let mut entry = archive.by_index(i)?;
+
+    if !entry.is_file()
+      || entry.size() > MAX_TEXT_FILE_SIZE
+      || !idgames_text::is_text_file(Path::new(entry.name()))
+    {
+      continue;
+    }
+
+    let mut contents = vec![];
+    entry.read_to_end(&mut contents)?;
+    text_files.push((entry.name().to_string(), contents));
+  }
+
+  Ok(text_files)
+}
+
+/// Files inside an archive that's kept as it is that a source port would
+/// load, like `maps/map01.wad`. Only for showing what's in it, they can't be
+/// passed to a port.
+fn list_archive_entries(
+  file: &Path,
+  archive_kind: ArchiveKind,
+  seven_zip_path: &str,
+) -> Result<Vec<String>, ImportError> {
+  let mut entries = if matches!(archive_kind, ArchiveKind::SevenZip) {
+    parse_seven_zip_listing(&run_seven_zip(
+      seven_zip_path,
+      &["l", "-slt", &database::path_to_string(file)?],
+    )?)
+  } else {
+    let mut archive = zip::ZipArchive::new(File::open(file)?)
+      .map_err(|err| ImportError::Io(io::Error::new(io::ErrorKind::InvalidData, err)))?;
+
+    let mut entries = vec![];
+    for i in 0..archive.len() {
+      let entry = archive
+        .by_index(i)
+        .map_err(|err| ImportError::Io(io::Error::new(io::ErrorKind::InvalidData, err)))?;
+
+      if entry.is_file() {
+        entries.push(entry.name().to_string());
+      }
+    }
+
+    entries
+  };
+
+  entries.retain(|x| file_load_order(Path::new(x)).is_some());
+  entries.sort();
+
+  Ok(entries)
+}
+
+/// The files in `7za l -slt` output. After the archive's own properties,
+/// every entry is a block of `Key = Value` lines.
+fn parse_seven_zip_listing(listing: &str) -> Vec<String> {
+  let mut entries = vec![];
+  let mut entry_path: Option<&str> = None;
+  let mut is_folder = false;
+
+  let entry_lines = listing
+    .lines()
+    .skip_while(|x| x.trim() != "----------")
+    .skip(1);
+  for line in entry_lines.chain([""]) {
+    let line = line.trim();
 
-  let destination = games_directory.join(basename);
+    if line.is_empty() {
+      if let Some(entry_path) = entry_path.take() {
+        if !is_folder {
+          // Zips always use `/`, 7-Zip uses `\` on Windows.
+          entries.push(entry_path.replace('\\', "/"));
+        }
+      }
+      is_folder = false;
+    } else if let Some(value) = line.strip_prefix("Path = ") {
+      entry_path = Some(value);
+    } else if line == "Folder = +" || line.starts_with("Attributes = D") {
+      is_folder = true;
+    }
+  }
+
+  entries
+}
+
+fn list_imported_files(destination: &Path) -> Result<Vec<String>, WadpunkError> {
+  if destination.is_dir() {
+    let mut files: Vec<String> = vec![];
+    for disk_entry in read_dir(destination, true)? {
+      database::recurse_disk_entry(disk_entry, &mut files);
+    }
+
+    Ok(files)
+  } else if destination.exists() {
+    Ok(vec![database::path_to_string(destination)?])
+  } else {
+    Ok(vec![])
+  }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportProgressStatus {
+  Importing,
+  Imported,
+  Skipped,
+  Failed,
+}
+
+/// Sent to the frontend once before and once after every item in a batch.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportProgress {
+  pub file_path: String,
+  pub index: usize,
+  pub total: usize,
+  pub bytes_processed: u64,
+  pub total_bytes: u64,
+  pub status: ImportProgressStatus,
+  pub game_id: Option<String>,
+  pub error: Option<String>,
+}
+
+/// Imports every path in `files`. With `recursive`, directories are walked
+/// and every WAD / archive found in them is imported on its own, instead of
+/// the directory becoming a single game. Loose files sharing a stem become a
+/// single folder game either way.
+pub fn import_files<F>(
+  store: &dyn LibraryStore,
+  files: &[String],
+  recursive: bool,
+  seven_zip_path: &str,
+  conflict_policy: ImportConflictPolicy,
+  keep_archive: bool,
+  mut on_progress: F,
+) -> ImportSummary
+where
+  F: FnMut(ImportProgress),
+{
+  let mut paths: Vec<PathBuf> = vec![];
+  for file in files {
+    let path = PathBuf::from(file);
+
+    if recursive && path.is_dir() {
+      collect_files(&path, &mut paths);
+    } else {
+      paths.push(path);
+    }
+  }
+
+  let items = group_loose_files(paths, recursive);
+
+  let sizes: Vec<u64> = items
+    .iter()
+    .map(|x| {
+      x.paths()
+        .iter()
+        .map(|x| fs_extra::dir::get_size(x).unwrap_or_default())
+        .sum()
+    })
+    .collect();
+  let total_bytes: u64 = sizes.iter().sum();
+  let total = items.len();
+
+  let mut bytes_processed = 0;
+  let mut summary = ImportSummary {
+    imported_count: 0,
+    skipped_count: 0,
+    failed_count: 0,
+    game_ids: vec![],
+    warnings: vec![],
+    errors: vec![],
+  };
+
+  for (index, (item, size)) in items.iter().zip(sizes).enumerate() {
+    let file_path = item.display_path().to_string_lossy().to_string();
+
+    let progress = ImportProgress {
+      file_path: file_path.clone(),
+      index,
+      total,
+      bytes_processed,
+      total_bytes,
+      status: ImportProgressStatus::Importing,
+      game_id: None,
+      error: None,
+    };
+    on_progress(progress.clone());
+
+    let result = match item {
+      ImportItem::Single(path) => database::path_to_string(path)
+        .map_err(ImportError::from)
+        .and_then(|file| import_file(store, &file, seven_zip_path, conflict_policy, keep_archive)),
+      ImportItem::Group(name, paths) => import_file_group(store, name, paths, conflict_policy),
+    };
+    bytes_processed += size;
+    let progress = ImportProgress {
+      bytes_processed,
+      ..progress
+    };
+
+    let progress = match result {
+      Ok(import_result) => {
+        summary.warnings.extend(
+          import_result
+            .warnings
+            .iter()
+            .map(|x| format!("{}: {}", file_path, x)),
+        );
+
+        if import_result.skipped {
+          summary.skipped_count += 1;
+        } else {
+          summary.imported_count += 1;
+        }
+        summary.game_ids.extend(import_result.game_id.clone());
+
+        ImportProgress {
+          status: if import_result.skipped {
+            ImportProgressStatus::Skipped
+          } else {
+            ImportProgressStatus::Imported
+          },
+          game_id: import_result.game_id,
+          error: None,
+          ..progress
+        }
+      }
+      Err(err) => {
+        summary.failed_count += 1;
+        summary.errors.push(format!("{}: {}", file_path, err));
+
+        ImportProgress {
+          status: ImportProgressStatus::Failed,
+          error: Some(err.to_string()),
+          ..progress
+        }
+      }
+    };
+
+    on_progress(progress);
+  }
+
+  summary
+}
+
+fn collect_files(directory: &Path, paths: &mut Vec<PathBuf>) {
+  let Ok(disk_entries) = read_dir(directory, false) else {
+    return;
+  };
+
+  let mut directory_paths: Vec<PathBuf> = disk_entries.into_iter().map(|x| x.path).collect();
+  directory_paths.sort();
+
+  for path in directory_paths {
+    if path.is_dir() {
+      collect_files(&path, paths);
+    } else {
+      paths.push(path);
+    }
+  }
+}
+
+/// Loose files in the same folder sharing a stem are grouped into a single
+/// item. Archives that get extracted, and folders, are always on their own.
+/// When `only_importable` is set, items without anything that looks like it
+/// could be a game on its own (text files, screenshots) are left out.
+fn group_loose_files(paths: Vec<PathBuf>, only_importable: bool) -> Vec<ImportItem> {
+  let mut groups: Vec<Vec<PathBuf>> = vec![];
+  let mut group_indexes: HashMap<(PathBuf, String), usize> = HashMap::new();
+
+  for path in paths {
+    let is_extracted_archive = path.is_file()
+      && !file_types::is_loadable_archive(&path)
+      && file_types::detect_file_type(&path)
+        .map(|x| ArchiveKind::from_file_type(x).is_some())
+        .unwrap_or_default();
+
+    let stem = path.file_stem().and_then(|x| x.to_str());
+
+    match (path.is_dir() || is_extracted_archive, path.parent(), stem) {
+      (false, Some(parent), Some(stem)) => {
+        let key = (parent.to_path_buf(), stem.to_lowercase());
+
+        if let Some(&index) = group_indexes.get(&key) {
+          groups[index].push(path);
+        } else {
+          group_indexes.insert(key, groups.len());
+          groups.push(vec![path]);
+        }
+      }
+      _ => groups.push(vec![path]),
+    }
+  }
+
+  groups
+    .into_iter()
+    .filter(|x| !only_importable || x.iter().any(|x| is_importable(x)))
+    .map(|mut x| {
+      if x.len() == 1 {
+        ImportItem::Single(x.remove(0))
+      } else {
+        // Named after the file that gets loaded, `MyMap.txt` shouldn't win
+        // over `mymap.wad`.
+        let named_after = x
+          .iter()
+          .find(|x| file_load_order(x).is_some())
+          .unwrap_or(&x[0]);
+        // Grouped by their stem, so they all have one.
+        let name = named_after
+          .file_stem()
+          .and_then(|x| x.to_str())
+          .unwrap_or_default()
+          .to_string();
+        ImportItem::Group(name, x)
+      }
+    })
+    .collect()
+}
+
+pub fn is_importable(path: &Path) -> bool {
+  path.is_dir()
+    || file_types::is_loadable_archive(path)
+    || file_types::detect_file_type(path)
+      .map(|x| x != FileType::Unknown)
+      .unwrap_or_default()
+}
+
+/// Decides where an import is written to, based on what is already in the
+/// games directory. `None` means the import should be skipped.
+fn resolve_import_target(
+  store: &dyn LibraryStore,
+  target_name: &str,
+  is_directory: bool,
+  conflict_policy: ImportConflictPolicy,
+  warnings: &mut Vec<String>,
+) -> Result<Option<ImportTarget>, ImportError> {
+  let games_directory = database::get_games_directory();
+  let existing = games_directory.join(target_name);
+
+  let to_game_id = |name: &str| {
+    if is_directory {
+      format!("{}/", name)
+    } else {
+      name.to_string()
+    }
+  };
+
+  // External games take up a name without anything in the games directory.
+  let is_taken = |name: &str| games_directory.join(name).exists() || is_external_game(store, name);
+
+  if !is_taken(target_name) {
+    return Ok(Some(ImportTarget {
+      destination: existing,
+      game_id: to_game_id(target_name),
+      replaces: None,
+    }));
+  }
+
+  match conflict_policy {
+    ImportConflictPolicy::OVERWRITE => {
+      // Hidden, so it doesn't show up as a game. Anything already there was
+      // left by an import that didn't get to finish.
+      let staging = games_directory.join(format!(".{}.importing", target_name));
+      remove_path(&staging)?;
+
+      Ok(Some(ImportTarget {
+        destination: staging,
+        game_id: to_game_id(target_name),
+        replaces: Some(existing),
+      }))
+    }
+
+    ImportConflictPolicy::SKIP => {
+      warnings.push(format!("{} already exists, skipped", target_name));
+
+      Ok(None)
+    }
+
+    ImportConflictPolicy::MERGE_AS_NEW_VERSION if existing.is_dir() => {
+      let version_directory = (2..)
+        .map(|i| existing.join(format!("version-{}", i)))
+        .find(|x| !x.exists())
+        .unwrap();
+
+      let destination = if is_directory {
+        version_directory
+      } else {
+        version_directory.join(target_name)
+      };
+
+      Ok(Some(ImportTarget {
+        destination,
+        game_id: format!("{}/", target_name),
+        replaces: None,
+      }))
+    }
+
+    ImportConflictPolicy::KEEP_BOTH | ImportConflictPolicy::MERGE_AS_NEW_VERSION => {
+      if conflict_policy == ImportConflictPolicy::MERGE_AS_NEW_VERSION {
+        warnings.push(format!(
+          "{} can't hold versions, kept both instead",
+          target_name
+        ));
+      }
+
+      // Keep the extension last so ports still recognize single files.
+      let path = Path::new(target_name);
+      let (stem, extension) = if is_directory {
+        (target_name, None)
+      } else {
+        (
+          path
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .unwrap_or(target_name),
+          path.extension().and_then(|x| x.to_str()),
+        )
+      };
+
+      let name = (2..)
+        .map(|i| match extension {
+          Some(extension) => format!("{} ({}).{}", stem, i, extension),
+          None => format!("{} ({})", stem, i),
+        })
+        .find(|x| !is_taken(x))
+        .unwrap();
+
+      Ok(Some(ImportTarget {
+        destination: games_directory.join(&name),
+        game_id: to_game_id(&name),
+        replaces: None,
+      }))
+    }
+  }
+}
+
+/// Swaps a staged overwrite in for the game it replaces, and returns where the
+/// import's files ended up. The old game is only deleted once the new one is
+/// in place, a failed swap puts it back.
+fn commit_import_target(
+  store: &dyn LibraryStore,
+  target: &ImportTarget,
+  warnings: &mut Vec<String>,
+) -> Result<PathBuf, ImportError> {
+  let Some(existing) = &target.replaces else {
+    return Ok(target.destination.clone());
+  };
+
+  let target_name = get_file_name(existing)?;
+  let old = existing.with_file_name(format!(".{}.old", target_name));
+  remove_path(&old)?;
+
+  if existing.exists() {
+    fs::rename(existing, &old)?;
+  }
+
+  if target.destination.exists() {
+    if let Err(err) = fs::rename(&target.destination, existing) {
+      let _ = fs::rename(&old, existing);
+
+      return Err(err.into());
+    }
+  }
+
+  // The file state points at the old files, it's rebuilt for the new ones,
+  // along with the IWAD when it was picked for the old ones. For external
+  // games only the reference is dropped, never the files it points to.
+  let mut db_game = store.load_game_meta(&target.game_id)?;
+  if is_external_game(store, target_name) {
+    db_game.external_path = None;
+  }
+  db_game.previous_file_state = None;
+  if db_game.iwad_id_inferred == Some(true) {
+    db_game.iwad_id = None;
+    db_game.iwad_id_inferred = None;
+  }
+  store.save_game(db_game)?;
+
+  if let Err(err) = remove_path(&old) {
+    warnings.push(format!("could not remove the old {}: {}", target_name, err));
+  }
+
+  warnings.push(format!("overwrote existing {}", target_name));
+
+  Ok(existing.clone())
+}
+
+/// Removes a file or a whole folder, if there's anything there.
+fn remove_path(path: &Path) -> io::Result<()> {
+  if path.is_dir() {
+    fs::remove_dir_all(path)
+  } else if path.exists() {
+    fs::remove_file(path)
+  } else {
+    Ok(())
+  }
+}
+
+/// The name an import is kept under. Paths like `/` or `..` don't have one.
+fn get_file_name(path: &Path) -> Result<&str, WadpunkError> {
+  path
+    .file_name()
+    .and_then(|x| x.to_str())
+    .ok_or_else(|| WadpunkError::InvalidInput(format!("{:?} can't be imported", path)))
+}
 
-  create_dir_all(&destination).unwrap();
+fn is_external_game(store: &dyn LibraryStore, name: &str) -> bool {
+  store.find_external_path(name).is_some()
+}
+
+fn extract_archive(
+  file: &str,
+  archive_kind: ArchiveKind,
+  basename: &str,
+  destination: &Path,
+  seven_zip_path: &str,
+) -> Result<(), ImportError> {
+  match archive_kind {
+    ArchiveKind::Zip => extract_zip(file, destination),
+    ArchiveKind::Tar => extract_tar(File::open(file)?, destination),
+    ArchiveKind::Gzip => extract_compressed(
+      flate2::read::MultiGzDecoder::new(BufReader::new(File::open(file)?)),
+      basename,
+      destination,
+    ),
+    ArchiveKind::Bzip2 => extract_compressed(
+      bzip2::read::MultiBzDecoder::new(BufReader::new(File::open(file)?)),
+      basename,
+      destination,
+    ),
+    ArchiveKind::Xz => extract_compressed(
+      xz2::read::XzDecoder::new_multi_decoder(BufReader::new(File::open(file)?)),
+      basename,
+      destination,
+    ),
+    ArchiveKind::Zstd => extract_compressed(
+      zstd::stream::read::Decoder::new(File::open(file)?)?,
+      basename,
+      destination,
+    ),
+    ArchiveKind::SevenZip => extract_with_seven_zip(file, destination, seven_zip_path, &[]),
+  }
+}
+
+fn extract_zip(file: &str, destination: &Path) -> Result<(), ImportError> {
+  let mut archive = zip::ZipArchive::new(File::open(file)?)
+    .map_err(|err| ImportError::Io(io::Error::new(io::ErrorKind::InvalidData, err)))?;
+  let mut entry_errors: Vec<ExtractEntryError> = vec![];
+
+  for i in 0..archive.len() {
+    let mut entry = match archive.by_index(i) {
+      Ok(entry) => entry,
+      Err(err) => {
+        entry_errors.push(ExtractEntryError {
+          entry: format!("#{}", i),
+          message: err.to_string(),
+        });
+        continue;
+      }
+    };
+
+    // `enclosed_name` is `None` for entries that would escape the destination.
+    let Some(relative) = entry.enclosed_name().map(|x| x.to_path_buf()) else {
+      entry_errors.push(ExtractEntryError {
+        entry: entry.name().to_string(),
+        message: "unsafe path".to_string(),
+      });
+      continue;
+    };
+    let entry_destination = destination.join(relative);
+
+    let result = if entry.is_dir() {
+      create_dir_all(&entry_destination)
+    } else {
+      write_entry(&mut entry, &entry_destination)
+    };
+
+    if let Err(err) = result {
+      entry_errors.push(ExtractEntryError {
+        entry: entry.name().to_string(),
+        message: err.to_string(),
+      });
+    }
+  }
+
+  if entry_errors.is_empty() {
+    Ok(())
+  } else {
+    Err(ImportError::Entries(entry_errors))
+  }
+}
+
+fn extract_tar<R: Read>(reader: R, destination: &Path) -> Result<(), ImportError> {
+  let mut archive = tar::Archive::new(reader);
+  let mut entry_errors: Vec<ExtractEntryError> = vec![];
+
+  for entry in archive.entries()? {
+    let mut entry = match entry {
+      Ok(entry) => entry,
+      // A broken header means the rest of the stream can't be trusted.
+      Err(err) => {
+        entry_errors.push(ExtractEntryError {
+          entry: "?".to_string(),
+          message: err.to_string(),
+        });
+        break;
+      }
+    };
+
+    let entry_name = entry
+      .path()
+      .map(|x| x.to_string_lossy().to_string())
+      .unwrap_or_default();
+
+    // `unpack_in` refuses, and returns `false` for, entries that would escape
+    // the destination.
+    match entry.unpack_in(destination) {
+      Ok(true) => {}
+      Ok(false) => entry_errors.push(ExtractEntryError {
+        entry: entry_name,
+        message: "unsafe path".to_string(),
+      }),
+      Err(err) => entry_errors.push(ExtractEntryError {
+        entry: entry_name,
+        message: err.to_string(),
+      }),
+    }
+  }
+
+  if entry_errors.is_empty() {
+    Ok(())
+  } else {
+    Err(ImportError::Entries(entry_errors))
+  }
+}
+
+/// gz / bz2 / xz / zst are usually tarballs, but can also be a single
+/// compressed file, like `mymap.wad.gz`.
+fn extract_compressed<R: Read>(
+  reader: R,
+  basename: &str,
+  destination: &Path,
+) -> Result<(), ImportError> {
+  let mut reader = BufReader::new(reader);
+  let mut header = vec![];
+  (&mut reader)
+    .take(TAR_MAGIC_OFFSET as u64 + 5)
+    .read_to_end(&mut header)?;
+
+  let is_tar = header.get(TAR_MAGIC_OFFSET..) == Some(b"ustar".as_slice());
+  let mut reader = io::Cursor::new(header).chain(reader);
+
+  if is_tar {
+    extract_tar(reader, destination)
+  } else {
+    write_entry(&mut reader, &destination.join(basename))?;
+
+    Ok(())
+  }
+}
+
+/// `filters` limit what's extracted, like `["*.txt", "-r"]`. Everything is
+/// extracted when they're empty.
+fn extract_with_seven_zip(
+  file: &str,
+  destination: &Path,
+  seven_zip_path: &str,
+  filters: &[&str],
+) -> Result<(), ImportError> {
+  let destination_arg = format!("-o{}", database::path_to_string(destination)?);
+  let mut args = vec!["x", "-aoa", &destination_arg, file];
+  args.extend(filters);
+
+  run_seven_zip(seven_zip_path, &args)?;
+
+  Ok(())
+}
+
+/// 7-Zip's output, or what it printed when it failed.
+fn run_seven_zip(seven_zip_path: &str, args: &[&str]) -> Result<String, ImportError> {
+  let output = Command::new(seven_zip_path).args(args).output()?;
+
+  if !output.status.success() {
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    return Err(ImportError::SevenZip(if !stderr.is_empty() {
+      stderr
+    } else if !stdout.is_empty() {
+      stdout
+    } else {
+      output.status.to_string()
+    }));
+  }
+
+  Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn write_entry<R: Read>(reader: &mut R, path: &Path) -> io::Result<()> {
+  if let Some(parent) = path.parent() {
+    create_dir_all(parent)?;
+  }
 
-  Command::new(seven_zip_path)
-    .args([
-      "x",
-      "-aoa",
-      &format!("-o{}", destination.to_str().unwrap()),
-      file,
-    ])
-    .status()
+  io::copy(reader, &mut File::create(path)?)?;
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+
+  use super::*;
+  use crate::database::DbSourcePort;
+  use crate::library_store::MemoryLibraryStore;
+
+  fn write_pwad(directory: &Path, file_name: &str) -> String {
+    fs::create_dir_all(directory).unwrap();
+
+    let path = directory.join(file_name);
+    fs::write(&path, b"PWAD\x00\x00\x00\x00\x0c\x00\x00\x00").unwrap();
+
+    path.to_str().unwrap().to_string()
+  }
+
+  fn import(store: &dyn LibraryStore, file: &str, conflict_policy: ImportConflictPolicy) {
+    import_file(store, file, "7za", conflict_policy, false).unwrap();
+  }
+
+  #[test]
+  fn rebuilds_the_file_state_when_overwriting() {
+    let data_directory = database::use_test_data_directory("overwrite-file-state");
+    let file = write_pwad(&data_directory.join("inbox"), "mymap.wad");
+    let store = MemoryLibraryStore::default();
+
+    import(&store, &file, ImportConflictPolicy::KEEP_BOTH);
+
+    let mut db_game = store.load_game_meta("mymap.wad").unwrap();
+    db_game.previous_file_state = Some(vec![]);
+    db_game.iwad_id = Some("doom2.wad".to_string());
+    db_game.iwad_id_inferred = Some(true);
+    store.save_game(db_game).unwrap();
+
+    import(&store, &file, ImportConflictPolicy::OVERWRITE);
+
+    let db_game = store.load_game_meta("mymap.wad").unwrap();
+    let file_state = db_game.previous_file_state.unwrap();
+    assert_eq!(file_state.len(), 1);
+    assert!(file_state[0].is_enabled);
+    assert_eq!(file_state[0].relative, "mymap.wad");
+    // There's no IWAD in the library to pick again.
+    assert_eq!(db_game.iwad_id, None);
+  }
+
+  #[test]
+  fn keeps_the_iwad_the_user_picked_when_overwriting() {
+    let data_directory = database::use_test_data_directory("overwrite-picked-iwad");
+    let file = write_pwad(&data_directory.join("inbox"), "mymap.wad");
+    let store = MemoryLibraryStore::default();
+
+    import(&store, &file, ImportConflictPolicy::KEEP_BOTH);
+
+    let mut db_game = store.load_game_meta("mymap.wad").unwrap();
+    db_game.iwad_id = Some("doom2.wad".to_string());
+    store.save_game(db_game).unwrap();
+
+    import(&store, &file, ImportConflictPolicy::OVERWRITE);
+
+    let db_game = store.load_game_meta("mymap.wad").unwrap();
+    assert_eq!(db_game.iwad_id.as_deref(), Some("doom2.wad"));
+  }
+
+  #[test]
+  fn adds_companion_files_to_the_folder_game_with_their_name() {
+    let data_directory = database::use_test_data_directory("companion-files");
+    let inbox_directory = data_directory.join("inbox");
+    fs::create_dir_all(database::get_games_directory().join("mymap")).unwrap();
+    let store = MemoryLibraryStore::default();
+
+    fs::create_dir_all(&inbox_directory).unwrap();
+    let patch = inbox_directory.join("mymap.deh");
+    fs::write(&patch, "Patch File for DeHackEd v3.0").unwrap();
+    let import_result = import_file(
+      &store,
+      patch.to_str().unwrap(),
+      "7za",
+      ImportConflictPolicy::SKIP,
+      false,
+    )
+    .unwrap();
+
+    assert_eq!(import_result.game_id.as_deref(), Some("mymap/"));
+    assert!(import_result.joined_existing_game);
+
+    // A WAD is a game of its own, even with the same name.
+    let wad = write_pwad(&inbox_directory, "mymap.wad");
+    let import_result =
+      import_file(&store, &wad, "7za", ImportConflictPolicy::SKIP, false).unwrap();
+
+    assert_eq!(import_result.game_id.as_deref(), Some("mymap.wad"));
+    assert!(!import_result.joined_existing_game);
+    assert!(!database::get_games_directory()
+      .join("mymap")
+      .join("mymap.wad")
+      .exists());
+  }
+
+  #[test]
+  fn lists_the_loadable_files_in_kept_archives() {
+    let data_directory = database::use_test_data_directory("kept-archive-entries");
+    let inbox_directory = data_directory.join("inbox");
+    fs::create_dir_all(&inbox_directory).unwrap();
+    let store = MemoryLibraryStore::default();
+    store
+      .save_source_port(DbSourcePort {
+        schema_version: 0,
+        id: Some("gzdoom".to_string()),
+        command: Some(vec![]),
+        known_source_port_id: "gzdoom".to_string(),
+        is_default: Some(true),
+      })
+      .unwrap();
+
+    let archive_path = inbox_directory.join("mymap.zip");
+    let mut archive = zip::ZipWriter::new(File::create(&archive_path).unwrap());
+    archive
+      .add_directory("maps/", zip::write::FileOptions::default())
+      .unwrap();
+    for name in ["maps/map01.wad", "mymap.deh", "mymap.txt"] {
+      archive
+        .start_file(name, zip::write::FileOptions::default())
+        .unwrap();
+      archive.write_all(b"PWAD").unwrap();
+    }
+    archive.finish().unwrap();
+
+    import_file(
+      &store,
+      archive_path.to_str().unwrap(),
+      "7za",
+      ImportConflictPolicy::SKIP,
+      true,
+    )
     .unwrap();
+
+    let game_files = store.find_all_game_files("mymap.zip");
+    assert_eq!(game_files.len(), 1);
+    assert!(game_files[0].path.ends_with("mymap.zip"));
+    assert_eq!(
+      game_files[0].archive_entries,
+      vec!["maps/map01.wad", "mymap.deh"]
+    );
+  }
+
+  #[test]
+  fn parses_seven_zip_listings() {
+    let listing = "\
+7-Zip (a) 23.01 (x64) : Copyright (c) 1999-2023 Igor Pavlov : 2023-06-20
+
+Listing archive: mymap.7z
+
+--
+Path = mymap.7z
+Type = 7z
+Physical Size = 1234
+
+----------
+Path = maps
+Folder = +
+Attributes = D
+
+Path = maps\\map01.wad
+Folder = -
+Size = 4
+
+Path = mymap.txt
+Folder = -
+Size = 4
+";
+
+    assert_eq!(
+      parse_seven_zip_listing(listing),
+      vec!["maps/map01.wad", "mymap.txt"]
+    );
+  }
 }
@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use notify::EventKind;
+use notify::RecommendedWatcher;
+use notify::RecursiveMode;
+use notify::Watcher;
+use serde::Serialize;
+use tauri::AppHandle;
+use tauri::Manager;
+
+use crate::database;
+use crate::error::WadpunkError;
+use crate::graphql::datasource::resolve_seven_zip_path;
+use crate::graphql::generated::ImportConflictPolicy;
+use crate::importer;
+use crate::importer::ImportError;
+use crate::library_issues;
+use crate::library_store::LibraryStore;
+use crate::library_store::SharedLibraryStore;
+
+// A file has to stop changing for this long before it's imported. Browsers
+// and download managers write in chunks, and some don't use a partial name.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Downloads that are still running.
+const PARTIAL_EXTENSIONS: [&str; 6] = ["part", "crdownload", "download", "partial", "tmp", "!qb"];
+
+// Dropping the watcher closes its channel, which stops the thread importing
+// from it.
+static WATCHER: Mutex<Option<RecommendedWatcher>> = Mutex::new(None);
+
+/// Sent to the frontend after each file an inbox picked up.
+#[derive(Debug, Clone, Serialize)]
+pub struct InboxImport {
+  pub file_path: String,
+  pub game_id: Option<String>,
+  pub skipped: bool,
+  pub warnings: Vec<String>,
+  pub error: Option<String>,
+}
+
+struct PendingFile {
+  size: u64,
+  modified: Option<SystemTime>,
+  unchanged_since: Instant,
+}
+
+/// Watches the inbox directories from the settings, replacing whatever was
+/// being watched before. Only files that show up while watching are imported,
+/// not the ones already there.
+pub fn start(app_handle: AppHandle, store: SharedLibraryStore) {
+  let mut current = WATCHER.lock().unwrap();
+  *current = None;
+
+  let db_app_settings = match database::load_app_settings() {
+    Ok(db_app_settings) => db_app_settings,
+    Err(err) => {
+      library_issues::record_warning(None, format!("could not watch the inboxes: {}", err));
+      return;
+    }
+  };
+
+  let inbox_directories: Vec<PathBuf> = db_app_settings
+    .inbox_directories
+    .unwrap_or_default()
+    .into_iter()
+    .filter(|x| !x.is_empty())
+    .map(PathBuf::from)
+    .collect();
+
+  if inbox_directories.is_empty() {
+    return;
+  }
+
+  let seven_zip_path = match resolve_seven_zip_path(&app_handle) {
+    Ok(seven_zip_path) => seven_zip_path,
+    Err(err) => {
+      library_issues::record_warning(None, format!("could not watch the inboxes: {}", err));
+      return;
+    }
+  };
+
+  let (sender, receiver) = mpsc::channel();
+  let mut watcher = match notify::recommended_watcher(sender) {
+    Ok(watcher) => watcher,
+    Err(err) => {
+      library_issues::record_warning(None, format!("could not watch the inboxes: {}", err));
+      return;
+    }
+  };
+
+  for inbox_directory in &inbox_directories {
+    if let Err(err) = watcher.watch(inbox_directory, RecursiveMode::NonRecursive) {
+      library_issues::record_warning(
+        None,
+        format!("could not watch the inbox {:?}: {}", inbox_directory, err),
+      );
+    }
+  }
+
+  *current = Some(watcher);
+
+  std::thread::spawn(move || {
+    let mut pending: HashMap<PathBuf, PendingFile> = HashMap::new();
+
+    loop {
+      match receiver.recv_timeout(POLL_INTERVAL) {
+        Ok(Ok(event)) => {
+          if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+            for path in event.paths {
+              if is_candidate(&path) {
+                pending.entry(path).or_insert(PendingFile {
+                  size: 0,
+                  modified: None,
+                  unchanged_since: Instant::now(),
+                });
+              }
+            }
+          }
+        }
+        Ok(Err(err)) => {
+          library_issues::record_warning(None, format!("inbox watcher error: {}", err))
+        }
+        Err(RecvTimeoutError::Timeout) => {}
+        Err(RecvTimeoutError::Disconnected) => break,
+      }
+
+      let settled = take_settled(&mut pending);
+
+      for path in settled {
+        let inbox_import = import(store.as_ref(), &path, &seven_zip_path);
+        let _ = app_handle.emit_all("inbox-import", inbox_import);
+      }
+    }
+  });
+}
+
+/// Removes and returns the pending files that haven't changed for
+/// `SETTLE_TIME`. Files that have gone away are forgotten.
+fn take_settled(pending: &mut HashMap<PathBuf, PendingFile>) -> Vec<PathBuf> {
+  let mut settled = vec![];
+
+  pending.retain(|path, pending_file| {
+    let Ok(metadata) = fs::metadata(path) else {
+      return false;
+    };
+
+    let modified = metadata.modified().ok();
+    if metadata.len() != pending_file.size || modified != pending_file.modified {
+      pending_file.size = metadata.len();
+      pending_file.modified = modified;
+      pending_file.unchanged_since = Instant::now();
+
+      return true;
+    }
+
+    // Windows keeps files that are still being written locked.
+    if pending_file.unchanged_since.elapsed() < SETTLE_TIME || File::open(path).is_err() {
+      return true;
+    }
+
+    settled.push(path.clone());
+    false
+  });
+
+  settled
+}
+
+fn is_candidate(path: &Path) -> bool {
+  let Some(file_name) = path.file_name().and_then(|x| x.to_str()) else {
+    return false;
+  };
+
+  let is_partial = path
+    .extension()
+    .and_then(|x| x.to_str())
+    .map(|x| PARTIAL_EXTENSIONS.contains(&x.to_lowercase().as_str()))
+    .unwrap_or_default();
+
+  !file_name.starts_with('.') && !is_partial && path.is_file()
+}
+
+fn import(store: &dyn LibraryStore, path: &Path, seven_zip_path: &str) -> InboxImport {
+  let file_path = path.to_string_lossy().to_string();
+
+  // Only known file types, an inbox like `~/Downloads` gets everything.
+  if !importer::is_importable(path) {
+    return InboxImport {
+      file_path,
+      game_id: None,
+      skipped: true,
+      warnings: vec![],
+      error: None,
+    };
+  }
+
+  // Files already in the library are left alone, a download that's touched
+  // again shouldn't turn into a second copy.
+  let result = database::path_to_string(path)
+    .map_err(ImportError::from)
+    .and_then(|file| {
+      importer::import_file(
+        store,
+        &file,
+        seven_zip_path,
+        ImportConflictPolicy::SKIP,
+        false,
+      )
+    });
+
+  match result {
+    Ok(import_result) => {
+      let mut warnings = import_result.warnings;
+
+      if !import_result.skipped {
+        if let Err(err) = move_to_processed(path) {
+          warnings.push(format!(
+            "could not move {} to the processed folder: {}",
+            file_path, err
+          ));
+        }
+      }
+
+      InboxImport {
+        file_path,
+        game_id: import_result.game_id,
+        skipped: import_result.skipped,
+        warnings,
+        error: None,
+      }
+    }
+    Err(err) => InboxImport {
+      file_path,
+      game_id: None,
+      skipped: false,
+      warnings: vec![],
+      error: Some(err.to_string()),
+    },
+  }
+}
+
+/// Moves an imported file out of the inbox, when there's a processed folder
+/// to move it to. A relative folder is inside the inbox the file came from.
+fn move_to_processed(path: &Path) -> Result<(), WadpunkError> {
+  let Some(processed_directory) = database::load_app_settings()?
+    .inbox_processed_directory
+    .filter(|x| !x.is_empty())
+  else {
+    return Ok(());
+  };
+
+  // Only files with a name that's valid UTF-8 are picked up, see
+  // `is_candidate`.
+  let (Some(inbox_directory), Some(file_name)) =
+    (path.parent(), path.file_name().and_then(|x| x.to_str()))
+  else {
+    return Ok(());
+  };
+
+  let processed_directory = inbox_directory.join(processed_directory);
+  fs::create_dir_all(&processed_directory)?;
+
+  let mut destination = processed_directory.join(file_name);
+
+  let mut counter = 2;
+  while destination.exists() {
+    let stem = Path::new(file_name)
+      .file_stem()
+      .and_then(|x| x.to_str())
+      .unwrap_or(file_name);
+    let new_file_name = match Path::new(file_name).extension().and_then(|x| x.to_str()) {
+      Some(extension) => format!("{} ({}).{}", stem, counter, extension),
+      None => format!("{} ({})", stem, counter),
+    };
+
+    destination = processed_directory.join(new_file_name);
+    counter += 1;
+  }
+
+  // Renaming fails across drives, like an inbox on a different disk.
+  if fs::rename(path, &destination).is_err() {
+    fs::copy(path, &destination)?;
+    fs::remove_file(path)?;
+  }
+
+  Ok(())
+}
@@ -0,0 +1,448 @@
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::path::Path;
+
+use crate::file_types;
+use crate::file_types::FileType;
+use crate::idgames_text;
+
+// Each WAD directory entry is a lump offset, a lump size and an 8 byte name.
+const WAD_DIRECTORY_ENTRY_SIZE: usize = 16;
+
+// Anything with more lumps than this is more likely a broken header.
+const MAX_WAD_LUMPS: usize = 65536;
+
+// GAMEINFO and IWADINFO are a few lines of text. Bigger ones aren't read, the
+// size comes straight from the file.
+const MAX_INFO_LUMP_SIZE: usize = 64 * 1024;
+
+// The IWADs each kind of map marker could be for, most likely first.
+const EPISODE_IWADS: [&str; 3] = ["doom.wad", "freedoom1.wad", "heretic.wad"];
+const HERETIC_EPISODE_IWADS: [&str; 3] = ["heretic.wad", "doom.wad", "freedoom1.wad"];
+const MAP_IWADS: [&str; 4] = ["doom2.wad", "freedoom2.wad", "plutonia.wad", "tnt.wad"];
+const HEXEN_MAP_IWADS: [&str; 1] = ["hexen.wad"];
+
+// Lump names, with the contents of the lumps worth reading.
+type NamedLumps = Vec<(String, Option<Vec<u8>>)>;
+
+struct WadLump {
+  name: String,
+  offset: u64,
+  size: usize,
+}
+
+/// What a game's contents say about the IWAD it needs, as IWAD file names,
+/// most likely first. An IWAD named in a GAMEINFO lump wins over `text_hint`
+/// (usually from the idgames text file), which wins over map names.
+pub fn infer_required_iwads(files: &[String], text_hint: Option<&str>) -> Vec<String> {
+  let mut declared: Vec<String> = vec![];
+  let mut map_names: Vec<String> = vec![];
+  let mut is_hexen_format = false;
+  let mut is_heretic = false;
+
+  for file in files {
+    let path = Path::new(file);
+
+    let Ok(file_type) = file_types::detect_file_type(path) else {
+      continue;
+    };
+
+    let result = match file_type {
+      FileType::Pwad => read_wad(path).map(|x| Some((x, vec![]))),
+      FileType::Zip => read_zip(path).map(Some),
+      _ => Ok(None),
+    };
+
+    let Ok(Some((lumps, zip_map_names))) = result else {
+      continue;
+    };
+
+    for (name, contents) in &lumps {
+      let Some(contents) = contents else {
+        continue;
+      };
+
+      match name.as_str() {
+        "GAMEINFO" => declared.extend(iwads_from_gameinfo(contents)),
+        "IWADINFO" => declared.extend(iwads_from_iwadinfo(contents)),
+        _ => {}
+      }
+    }
+
+    is_hexen_format |= lumps.iter().any(|(name, _)| name == "BEHAVIOR");
+    // Heretic's music lumps are `MUS_*`, Doom's are `D_*`.
+    is_heretic |= lumps.iter().any(|(name, _)| name.starts_with("MUS_"));
+
+    map_names.extend(lumps.into_iter().map(|(name, _)| name));
+    map_names.extend(zip_map_names);
+  }
+
+  let has_episode_maps = map_names.iter().any(|x| is_episode_map_name(x));
+  let has_maps = map_names.iter().any(|x| is_map_name(x));
+
+  let from_maps: &[&str] = if has_maps && is_hexen_format {
+    &HEXEN_MAP_IWADS
+  } else if has_maps {
+    &MAP_IWADS
+  } else if has_episode_maps && is_heretic {
+    &HERETIC_EPISODE_IWADS
+  } else if has_episode_maps {
+    &EPISODE_IWADS
+  } else {
+    &[]
+  };
+
+  let mut iwads: Vec<String> = vec![];
+  let candidates = declared
+    .into_iter()
+    .chain(text_hint.map(|x| x.to_string()))
+    .chain(from_maps.iter().map(|x| x.to_string()));
+
+  for candidate in candidates {
+    let candidate = candidate.to_lowercase();
+
+    if !iwads.contains(&candidate) {
+      iwads.push(candidate);
+    }
+  }
+
+  iwads
+}
+
+fn read_wad(path: &Path) -> io::Result<NamedLumps> {
+  let mut file = File::open(path)?;
+  let file_size = file.metadata()?.len();
+
+  let mut header = [0u8; 12];
+  file.read_exact(&mut header)?;
+
+  let lump_count = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+  let directory_offset = u32::from_le_bytes(header[8..12].try_into().unwrap()) as u64;
+
+  if lump_count > MAX_WAD_LUMPS {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "too many lumps"));
+  }
+  if directory_offset + (lump_count * WAD_DIRECTORY_ENTRY_SIZE) as u64 > file_size {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      "directory is past the end of the file",
+    ));
+  }
+
+  let mut directory = vec![0u8; lump_count * WAD_DIRECTORY_ENTRY_SIZE];
+  file.seek(SeekFrom::Start(directory_offset))?;
+  file.read_exact(&mut directory)?;
+
+  let lumps: Vec<WadLump> = directory
+    .chunks_exact(WAD_DIRECTORY_ENTRY_SIZE)
+    .map(|x| WadLump {
+      offset: u32::from_le_bytes(x[0..4].try_into().unwrap()) as u64,
+      size: u32::from_le_bytes(x[4..8].try_into().unwrap()) as usize,
+      name: String::from_utf8_lossy(&x[8..16])
+        .trim_end_matches('\0')
+        .to_uppercase(),
+    })
+    .collect();
+
+  let mut named_lumps = vec![];
+  for lump in lumps {
+    let is_readable =
+      lump.size <= MAX_INFO_LUMP_SIZE && lump.offset + lump.size as u64 <= file_size;
+
+    let contents = if is_info_lump(&lump.name) && is_readable {
+      let mut contents = vec![0u8; lump.size];
+      file.seek(SeekFrom::Start(lump.offset))?;
+      file.read_exact(&mut contents)?;
+
+      Some(contents)
+    } else {
+      None
+    };
+
+    named_lumps.push((lump.name, contents));
+  }
+
+  Ok(named_lumps)
+}
+
+/// Same as `read_wad`, plus the names of maps stored as `maps/*.wad`.
+fn read_zip(path: &Path) -> io::Result<(NamedLumps, Vec<String>)> {
+  let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+
+  let mut lumps = vec![];
+  let mut map_names = vec![];
+
+  for i in 0..archive.len() {
+    let mut entry = archive.by_index(i)?;
+    let entry_path = entry.name().to_lowercase();
+    let entry_path = Path::new(&entry_path);
+
+    let Some(stem) = entry_path.file_stem().and_then(|x| x.to_str()) else {
+      continue;
+    };
+    let stem = stem.to_uppercase();
+    let parent = entry_path
+      .parent()
+      .and_then(|x| x.to_str())
+      .unwrap_or_default();
+
+    if parent == "maps" {
+      map_names.push(stem);
+    } else if parent.is_empty() && is_info_lump(&stem) {
+      // The size in the header can't be trusted either, hence the `take`.
+      if entry.size() > MAX_INFO_LUMP_SIZE as u64 {
+        continue;
+      }
+
+      let mut contents = vec![];
+      (&mut entry)
+        .take(MAX_INFO_LUMP_SIZE as u64)
+        .read_to_end(&mut contents)?;
+
+      lumps.push((stem, Some(contents)));
+    }
+  }
+
+  Ok((lumps, map_names))
+}
+
+fn is_info_lump(name: &str) -> bool {
+  name == "GAMEINFO" || name == "IWADINFO"
+}
+
+/// `IWAD = "doom2.wad"`
+fn iwads_from_gameinfo(contents: &[u8]) -> Vec<String> {
+  idgames_text::decode_text(contents)
+    .lines()
+    .filter_map(|x| x.split_once('='))
+    .filter(|(key, _)| key.trim().eq_ignore_ascii_case("iwad"))
+    .map(|(_, value)| value.trim().trim_matches('"').to_string())
+    .filter(|x| !x.is_empty())
+    .collect()
+}
+
+/// IWADINFO describes IWADs rather than naming one, so go by the `Game` it
+/// is for, and whether it has Doom II style maps.
+fn iwads_from_iwadinfo(contents: &[u8]) -> Vec<String> {
+  let text = idgames_text::decode_text(contents);
+
+  text
+    .lines()
+    .filter_map(|x| x.split_once('='))
+    .filter(|(key, _)| key.trim().eq_ignore_ascii_case("game"))
+    .filter_map(|(_, value)| {
+      let game = value.trim().trim_matches('"').to_lowercase();
+
+      if game == "doom" && text.to_uppercase().contains("MAP01") {
+        Some("doom2.wad".to_string())
+      } else {
+        idgames_text::iwad_from_game(&game)
+      }
+    })
+    .collect()
+}
+
+/// `E1M1`
+fn is_episode_map_name(name: &str) -> bool {
+  let bytes = name.as_bytes();
+
+  bytes.len() == 4
+    && bytes[0] == b'E'
+    && bytes[1].is_ascii_digit()
+    && bytes[2] == b'M'
+    && bytes[3].is_ascii_digit()
+}
+
+/// `MAP01`
+fn is_map_name(name: &str) -> bool {
+  name.len() == 5 && name.starts_with("MAP") && name[3..].bytes().all(|x| x.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+
+  use super::*;
+
+  fn build_wad(lumps: &[(&str, &[u8])]) -> Vec<u8> {
+    let lumps_size: usize = lumps.iter().map(|(_, contents)| contents.len()).sum();
+
+    let mut wad = b"PWAD".to_vec();
+    wad.extend((lumps.len() as u32).to_le_bytes());
+    wad.extend(((12 + lumps_size) as u32).to_le_bytes());
+
+    let mut directory = vec![];
+    for (name, contents) in lumps {
+      directory.extend((wad.len() as u32).to_le_bytes());
+      directory.extend((contents.len() as u32).to_le_bytes());
+
+      let mut name = name.as_bytes().to_vec();
+      name.resize(8, 0);
+      directory.extend(name);
+
+      wad.extend(*contents);
+    }
+    wad.extend(directory);
+
+    wad
+  }
+
+  fn build_zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut zip = zip::ZipWriter::new(io::Cursor::new(vec![]));
+
+    for (name, contents) in entries {
+      zip
+        .start_file(*name, zip::write::FileOptions::default())
+        .unwrap();
+      zip.write_all(contents).unwrap();
+    }
+
+    zip.finish().unwrap().into_inner()
+  }
+
+  fn write_test_file(test_name: &str, file_name: &str, contents: &[u8]) -> String {
+    let directory = std::env::temp_dir().join(format!(
+      "wadpunk-iwad-inference-{}-{}",
+      test_name,
+      std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).unwrap();
+
+    let path = directory.join(file_name);
+    std::fs::write(&path, contents).unwrap();
+
+    path.to_str().unwrap().to_string()
+  }
+
+  #[test]
+  fn reads_lump_names_and_info_lumps() {
+    let wad = build_wad(&[("gameinfo", b"IWAD = \"tnt.wad\""), ("MAP01", b"map")]);
+    let path = write_test_file("lumps", "mymap.wad", &wad);
+
+    let lumps = read_wad(Path::new(&path)).unwrap();
+
+    assert_eq!(
+      lumps,
+      vec![
+        ("GAMEINFO".to_string(), Some(b"IWAD = \"tnt.wad\"".to_vec())),
+        ("MAP01".to_string(), None),
+      ]
+    );
+  }
+
+  #[test]
+  fn rejects_directories_past_the_end_of_the_file() {
+    let mut wad = build_wad(&[("MAP01", b"map")]);
+    // Claims a second lump the directory doesn't have room for.
+    wad[4] = 2;
+    let path = write_test_file("directory-past-end", "mymap.wad", &wad);
+
+    let err = read_wad(Path::new(&path)).unwrap_err();
+
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+  }
+
+  #[test]
+  fn rejects_too_many_lumps() {
+    let mut wad = build_wad(&[]);
+    wad[4..8].copy_from_slice(&(MAX_WAD_LUMPS as u32 + 1).to_le_bytes());
+    let path = write_test_file("too-many-lumps", "mymap.wad", &wad);
+
+    let err = read_wad(Path::new(&path)).unwrap_err();
+
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+  }
+
+  #[test]
+  fn skips_info_lumps_that_are_too_big_or_past_the_end() {
+    let big_gameinfo = vec![b' '; MAX_INFO_LUMP_SIZE + 1];
+    let mut wad = build_wad(&[
+      ("GAMEINFO", &big_gameinfo),
+      ("IWADINFO", b"Game = \"Doom\""),
+    ]);
+    // Points the IWADINFO lump past the end of the file.
+    let directory_offset = u32::from_le_bytes(wad[8..12].try_into().unwrap()) as usize;
+    let iwadinfo_entry = directory_offset + WAD_DIRECTORY_ENTRY_SIZE;
+    wad[iwadinfo_entry..iwadinfo_entry + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    let path = write_test_file("big-info-lumps", "mymap.wad", &wad);
+
+    let lumps = read_wad(Path::new(&path)).unwrap();
+
+    assert_eq!(
+      lumps,
+      vec![
+        ("GAMEINFO".to_string(), None),
+        ("IWADINFO".to_string(), None)
+      ]
+    );
+  }
+
+  #[test]
+  fn reads_map_names_and_info_lumps_from_zips() {
+    let big_gameinfo = vec![b' '; MAX_INFO_LUMP_SIZE + 1];
+    let zip = build_zip(&[
+      ("maps/map01.wad", b"map"),
+      ("IWADINFO.txt", b"Game = \"Heretic\""),
+      ("gameinfo.txt", &big_gameinfo),
+      ("textures/gameinfo.txt", b"IWAD = \"tnt.wad\""),
+    ]);
+    let path = write_test_file("zip", "mymap.pk3", &zip);
+
+    let (lumps, map_names) = read_zip(Path::new(&path)).unwrap();
+
+    assert_eq!(
+      lumps,
+      vec![("IWADINFO".to_string(), Some(b"Game = \"Heretic\"".to_vec()))]
+    );
+    assert_eq!(map_names, vec!["MAP01".to_string()]);
+  }
+
+  #[test]
+  fn infers_iwads_from_map_names() {
+    let doom2 = write_test_file("doom2", "mymap.wad", &build_wad(&[("MAP01", b"")]));
+    let doom = write_test_file("doom", "mymap.wad", &build_wad(&[("E1M1", b"")]));
+    let heretic = write_test_file(
+      "heretic",
+      "mymap.wad",
+      &build_wad(&[("E1M1", b""), ("MUS_E1M1", b"")]),
+    );
+    let hexen = write_test_file(
+      "hexen",
+      "mymap.wad",
+      &build_wad(&[("MAP01", b""), ("BEHAVIOR", b"")]),
+    );
+
+    assert_eq!(infer_required_iwads(&[doom2], None), MAP_IWADS.to_vec());
+    assert_eq!(infer_required_iwads(&[doom], None), EPISODE_IWADS.to_vec());
+    assert_eq!(
+      infer_required_iwads(&[heretic], None),
+      HERETIC_EPISODE_IWADS.to_vec()
+    );
+    assert_eq!(
+      infer_required_iwads(&[hexen], None),
+      HEXEN_MAP_IWADS.to_vec()
+    );
+  }
+
+  #[test]
+  fn prefers_gameinfo_over_the_text_hint_over_map_names() {
+    let wad = build_wad(&[("GAMEINFO", b"IWAD = \"Plutonia.wad\""), ("MAP01", b"")]);
+    let path = write_test_file("gameinfo", "mymap.wad", &wad);
+
+    assert_eq!(
+      infer_required_iwads(&[path], Some("tnt.wad")),
+      vec!["plutonia.wad", "tnt.wad", "doom2.wad", "freedoom2.wad"]
+    );
+  }
+
+  #[test]
+  fn skips_files_that_are_not_wads() {
+    let path = write_test_file("not-a-wad", "mymap.txt", b"Title : My Map");
+
+    assert!(infer_required_iwads(&[path], None).is_empty());
+  }
+}
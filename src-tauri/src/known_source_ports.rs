@@ -1,3 +1,11 @@
+use std::path::PathBuf;
+
+use tauri::api::path::config_dir;
+use tauri::api::path::data_dir;
+use tauri::api::path::document_dir;
+use tauri::api::path::home_dir;
+
+use crate::file_types::FileType;
 use crate::{database, graphql::generated::KnownSourcePort};
 
 pub struct BuildCommandArgs {
@@ -62,6 +70,61 @@ impl DbKnownSourcePort {
     }
   }
 
+  /// Archives the port loads as they are, the way it loads `.pk3` files.
+  pub fn supports_archive(&self, file_type: FileType) -> bool {
+    match self {
+      Self::GZDoom => matches!(file_type, FileType::Zip | FileType::SevenZip),
+      Self::EternityEngine => matches!(file_type, FileType::Zip),
+      Self::ChocolateDoom => false,
+      Self::DSDA => false,
+      Self::Woof => false,
+      Self::Zandronum => matches!(file_type, FileType::Zip | FileType::SevenZip),
+    }
+  }
+
+  /// Where the port keeps saves when it isn't given `-savedir` / `-save`.
+  /// Portable installs keep them next to the executable, which isn't known
+  /// here. That's the only way Chocolate Doom and Eternity run on Windows,
+  /// so there's nothing to look in for them there.
+  pub fn default_save_directories(&self) -> Vec<PathBuf> {
+    let home = home_dir().unwrap_or_default();
+    let config = config_dir().unwrap_or_else(|| home.join(".config"));
+    let data = data_dir().unwrap_or_else(|| home.join(".local").join("share"));
+    let documents = document_dir().unwrap_or_else(|| home.join("Documents"));
+    let application_support = home.join("Library").join("Application Support");
+
+    if cfg!(target_os = "windows") {
+      match self {
+        Self::GZDoom => vec![documents.join("My Games").join("GZDoom")],
+        Self::Zandronum => vec![documents.join("My Games").join("Zandronum")],
+        Self::DSDA => vec![config.join("dsda-doom")],
+        Self::Woof => vec![config.join("woof")],
+        Self::EternityEngine | Self::ChocolateDoom => vec![],
+      }
+    } else if cfg!(target_os = "macos") {
+      match self {
+        Self::GZDoom => vec![
+          documents.join("GZDoom").join("Savegames"),
+          application_support.join("gzdoom"),
+        ],
+        Self::EternityEngine => vec![application_support.join("Eternity")],
+        Self::ChocolateDoom => vec![application_support.join("chocolate-doom")],
+        Self::DSDA => vec![application_support.join("dsda-doom")],
+        Self::Woof => vec![application_support.join("woof")],
+        Self::Zandronum => vec![application_support.join("zandronum")],
+      }
+    } else {
+      match self {
+        Self::GZDoom => vec![config.join("gzdoom"), data.join("games").join("gzdoom")],
+        Self::EternityEngine => vec![config.join("eternity")],
+        Self::ChocolateDoom => vec![data.join("chocolate-doom")],
+        Self::DSDA => vec![home.join(".dsda-doom")],
+        Self::Woof => vec![data.join("woof")],
+        Self::Zandronum => vec![config.join("zandronum")],
+      }
+    }
+  }
+
   pub fn home_page_url(&self) -> String {
     match self {
       Self::GZDoom => "https://zdoom.org/".to_string(),
@@ -136,9 +199,7 @@ impl DbKnownSourcePort {
       }
     }
 
-    let save_dir = database::get_meta_directory()
-      .join(database::normalize_name_from_id(&args.game_id))
-      .join("saves")
+    let save_dir = database::get_game_saves_directory(&args.game_id)
       .to_str()
       .unwrap()
       .to_string();
@@ -0,0 +1,378 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use rusqlite::params;
+use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+use rusqlite::Result;
+
+use crate::database;
+use crate::database::DbGameMeta;
+use crate::database::DbPlaySession;
+use crate::migrations;
+use crate::migrations::Schema;
+
+// The JSON files under `Meta` stay the source of truth. This is a copy of
+// them that can be read in one query, keyed by the name of the game's `Meta`
+// folder, along with when each file was last modified.
+const SCHEMA: &str = "
+  CREATE TABLE IF NOT EXISTS games (
+    name TEXT PRIMARY KEY,
+    meta TEXT NOT NULL,
+    modified INTEGER NOT NULL
+  );
+
+  CREATE TABLE IF NOT EXISTS play_sessions (
+    name TEXT PRIMARY KEY,
+    sessions TEXT NOT NULL,
+    modified INTEGER NOT NULL
+  );
+";
+
+// Reopened when the data directory changes.
+static CONNECTION: Mutex<Option<(PathBuf, Connection)>> = Mutex::new(None);
+
+enum Table {
+  Games,
+  PlaySessions,
+}
+
+impl Table {
+  fn name(&self) -> &'static str {
+    match self {
+      Self::Games => "games",
+      Self::PlaySessions => "play_sessions",
+    }
+  }
+
+  fn value_column(&self) -> &'static str {
+    match self {
+      Self::Games => "meta",
+      Self::PlaySessions => "sessions",
+    }
+  }
+
+  fn file_name(&self) -> &'static str {
+    match self {
+      Self::Games => "meta.json",
+      Self::PlaySessions => "playSessions.json",
+    }
+  }
+
+  fn schema(&self) -> Schema {
+    match self {
+      Self::Games => Schema::GameMeta,
+      Self::PlaySessions => Schema::PlaySessions,
+    }
+  }
+
+  fn schema_version(&self) -> u32 {
+    match self {
+      Self::Games => migrations::GAME_META_SCHEMA_VERSION,
+      Self::PlaySessions => migrations::PLAY_SESSIONS_SCHEMA_VERSION,
+    }
+  }
+}
+
+fn with_connection<T, F>(f: F) -> Result<T>
+where
+  F: FnOnce(&mut Connection) -> Result<T>,
+{
+  let index_path = database::get_library_index_path();
+  let mut connection = CONNECTION.lock().unwrap();
+
+  let is_open = match &*connection {
+    Some((path, _)) => *path == index_path,
+    None => false,
+  };
+
+  if !is_open {
+    let new_connection = Connection::open(&index_path)?;
+    new_connection.execute_batch(SCHEMA)?;
+
+    *connection = Some((index_path, new_connection));
+  }
+
+  f(&mut connection.as_mut().unwrap().1)
+}
+
+/// Every indexed game's metadata, by `Meta` folder name. `None` for files
+/// that don't parse, `load_game_meta` and `read_external_game_meta` deal with
+/// those.
+pub fn load_games() -> Result<BTreeMap<String, Option<DbGameMeta>>> {
+  with_connection(|connection| {
+    let mut statement = connection.prepare_cached("SELECT name, meta FROM games")?;
+
+    let rows = statement.query_map([], |row| {
+      Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+
+    let mut db_games = BTreeMap::new();
+    for row in rows {
+      let (name, meta) = row?;
+
+      db_games.insert(name, serde_json::from_str::<DbGameMeta>(&meta).ok());
+    }
+
+    Ok(db_games)
+  })
+}
+
+/// `None` when the game's play sessions haven't been indexed yet, or the file
+/// changed since.
+pub fn load_play_sessions(name: &str) -> Result<Option<DbPlaySession>> {
+  let modified = file_modified(
+    &database::get_meta_directory()
+      .join(name)
+      .join(Table::PlaySessions.file_name()),
+  );
+
+  with_connection(|connection| {
+    let sessions: Option<String> = connection
+      .prepare_cached("SELECT sessions FROM play_sessions WHERE name = ?1 AND modified = ?2")?
+      .query_row(params![name, modified], |row| row.get(0))
+      .optional()?;
+
+    Ok(sessions.and_then(|x| serde_json::from_str::<DbPlaySession>(&x).ok()))
+  })
+}
+
+/// Called after `meta.json` is written, with what was written.
+pub fn index_game(name: &str, meta: &str) -> Result<()> {
+  index_file(Table::Games, name, meta)
+}
+
+/// Called after `playSessions.json` is written, with what was written.
+pub fn index_play_sessions(name: &str, sessions: &str) -> Result<()> {
+  index_file(Table::PlaySessions, name, sessions)
+}
+
+fn index_file(table: Table, name: &str, contents: &str) -> Result<()> {
+  let modified = file_modified(
+    &database::get_meta_directory()
+      .join(name)
+      .join(table.file_name()),
+  );
+
+  with_connection(|connection| upsert(connection, &table, name, contents, modified))
+}
+
+/// Catches the index up with changes made to the JSON files outside the app,
+/// like edits by hand or by a sync tool. Runs on startup and whenever the
+/// library is listed. Only files modified
+/// since they were indexed are read, along with files indexed before their
+/// schema's last migration, which get upgraded on disk.
+pub fn sync() -> Result<()> {
+  let meta_directory = database::get_meta_directory();
+
+  let names: Vec<String> = fs::read_dir(&meta_directory)
+    .map(|entries| {
+      entries
+        .flatten()
+        .filter(|x| x.path().is_dir())
+        .filter_map(|x| x.file_name().to_str().map(|x| x.to_string()))
+        .filter(|x| !x.starts_with('.'))
+        .collect()
+    })
+    .unwrap_or_default();
+
+  with_connection(|connection| {
+    let transaction = connection.transaction()?;
+
+    for table in [Table::Games, Table::PlaySessions] {
+      let indexed = load_modified(&transaction, &table)?;
+
+      for name in &names {
+        let path = meta_directory.join(name).join(table.file_name());
+        let modified = file_modified(&path);
+
+        if indexed.get(name) == Some(&(modified, table.schema_version())) {
+          continue;
+        }
+
+        match fs::read_to_string(&path) {
+          Ok(contents) => {
+            // Files that don't parse are indexed as they are, `load_games`
+            // reports them.
+            let contents =
+              migrations::migrate(&path, table.schema(), &contents).unwrap_or(contents);
+
+            upsert(&transaction, &table, name, &contents, file_modified(&path))?
+          }
+          Err(_) => delete(&transaction, &table, name)?,
+        }
+      }
+
+      // Games whose `Meta` folder is gone.
+      let names: HashSet<&String> = names.iter().collect();
+      for name in indexed.keys().filter(|x| !names.contains(x)) {
+        delete(&transaction, &table, name)?;
+      }
+    }
+
+    transaction.commit()
+  })
+}
+
+/// When each indexed file was modified, and the schema version it was
+/// indexed at.
+fn load_modified(connection: &Connection, table: &Table) -> Result<HashMap<String, (i64, u32)>> {
+  let mut statement = connection.prepare(&format!(
+    "SELECT name, modified, CASE WHEN json_valid({column})
+       THEN IFNULL(json_extract({column}, '$.schema_version'), 0) ELSE 0 END
+     FROM {table}",
+    table = table.name(),
+    column = table.value_column(),
+  ))?;
+
+  let rows = statement.query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))?;
+
+  rows.collect()
+}
+
+fn upsert(
+  connection: &Connection,
+  table: &Table,
+  name: &str,
+  contents: &str,
+  modified: i64,
+) -> Result<()> {
+  connection
+    .prepare_cached(&format!(
+      "INSERT INTO {table} (name, {column}, modified) VALUES (?1, ?2, ?3)
+       ON CONFLICT (name) DO UPDATE SET {column} = ?2, modified = ?3",
+      table = table.name(),
+      column = table.value_column(),
+    ))?
+    .execute(params![name, contents, modified])?;
+
+  Ok(())
+}
+
+fn delete(connection: &Connection, table: &Table, name: &str) -> Result<()> {
+  connection
+    .prepare_cached(&format!("DELETE FROM {} WHERE name = ?1", table.name()))?
+    .execute(params![name])?;
+
+  Ok(())
+}
+
+/// Nanoseconds since the epoch, 0 for files that don't exist.
+fn file_modified(path: &Path) -> i64 {
+  fs::metadata(path)
+    .and_then(|x| x.modified())
+    .ok()
+    .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+    .map(|x| x.as_nanos() as i64)
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::database::DbPlaySessionEntry;
+
+  fn write_meta_file(name: &str, file_name: &str, contents: &str) {
+    let directory = database::get_meta_directory().join(name);
+    fs::create_dir_all(&directory).unwrap();
+    fs::write(directory.join(file_name), contents).unwrap();
+  }
+
+  fn play_session() -> DbPlaySessionEntry {
+    DbPlaySessionEntry {
+      started_at: Some("2024-03-04T12:00:00+00:00".to_string()),
+      ended_at: Some("2024-03-04T12:01:00+00:00".to_string()),
+      source_port: None,
+      iwad_id: None,
+    }
+  }
+
+  #[test]
+  fn picks_up_metadata_changed_on_disk() {
+    database::use_test_data_directory("index-changed-meta");
+    fs::write(database::get_games_directory().join("mymap.wad"), "").unwrap();
+    assert_eq!(
+      database::find_all_games().unwrap()[0].name.as_deref(),
+      Some("mymap.wad")
+    );
+
+    write_meta_file(
+      "mymap.wad",
+      "meta.json",
+      r#"{"name":"My Map","installed_at":"2024-01-02T03:04:05+00:00"}"#,
+    );
+
+    let db_games = database::find_all_games().unwrap();
+    assert_eq!(db_games[0].name.as_deref(), Some("My Map"));
+    // Upgraded on disk by the sync, and indexed at the current version.
+    let indexed = with_connection(|x| load_modified(x, &Table::Games)).unwrap();
+    assert_eq!(indexed["mymap.wad"].1, migrations::GAME_META_SCHEMA_VERSION);
+  }
+
+  #[test]
+  fn reads_play_sessions_changed_on_disk() {
+    database::use_test_data_directory("index-changed-play-sessions");
+    database::record_game_play_session("mymap.wad", play_session()).unwrap();
+    assert_eq!(
+      database::load_game_play_sessions("mymap.wad")
+        .unwrap()
+        .sessions
+        .len(),
+      1
+    );
+
+    let sessions = serde_json::to_string(&DbPlaySession {
+      schema_version: migrations::PLAY_SESSIONS_SCHEMA_VERSION,
+      sessions: vec![play_session(), play_session()],
+    })
+    .unwrap();
+    write_meta_file("mymap.wad", "playSessions.json", &sessions);
+
+    assert_eq!(load_play_sessions("mymap.wad").unwrap().map(|_| ()), None);
+    assert_eq!(
+      database::load_game_play_sessions("mymap.wad")
+        .unwrap()
+        .sessions
+        .len(),
+      2
+    );
+  }
+
+  #[test]
+  fn keeps_games_that_were_never_played_indexed() {
+    database::use_test_data_directory("index-never-played");
+    write_meta_file(
+      "mymap.wad",
+      "meta.json",
+      r#"{"name":"mymap.wad","installed_at":"2024-01-02T03:04:05+00:00"}"#,
+    );
+
+    database::load_game_play_sessions("mymap.wad").unwrap();
+    sync().unwrap();
+
+    assert!(load_play_sessions("mymap.wad").unwrap().is_some());
+  }
+
+  #[test]
+  fn drops_games_whose_meta_folder_is_gone() {
+    database::use_test_data_directory("index-removed-game");
+    write_meta_file(
+      "mymap.wad",
+      "meta.json",
+      r#"{"name":"mymap.wad","installed_at":"2024-01-02T03:04:05+00:00"}"#,
+    );
+    sync().unwrap();
+    assert!(load_games().unwrap().contains_key("mymap.wad"));
+
+    fs::remove_dir_all(database::get_meta_directory().join("mymap.wad")).unwrap();
+    sync().unwrap();
+
+    assert!(!load_games().unwrap().contains_key("mymap.wad"));
+  }
+}
@@ -0,0 +1,284 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use chrono::Utc;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::database;
+use crate::error::WadpunkError;
+use crate::graphql::generated::LibraryIssue;
+
+// Kept next to the quarantined files.
+const ISSUES_FILE_NAME: &str = "issues.json";
+
+// The oldest ones are dropped.
+const MAX_ISSUES: usize = 200;
+
+// By data directory, they belong to the library they couldn't be written to.
+static UNRECORDED_ISSUES: Mutex<BTreeMap<PathBuf, Vec<DbLibraryIssue>>> =
+  Mutex::new(BTreeMap::new());
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct DbLibraryIssues {
+  #[serde(default)]
+  issues: Vec<DbLibraryIssue>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct DbLibraryIssue {
+  game_id: Option<String>,
+  message: String,
+  quarantined_path: Option<String>,
+  recorded_at: String,
+}
+
+impl DbLibraryIssue {
+  fn to_library_issue(&self) -> LibraryIssue {
+    LibraryIssue {
+      game_id: self.game_id.clone(),
+      message: self.message.clone(),
+      quarantined_path: self.quarantined_path.clone(),
+      recorded_at: self.recorded_at.clone(),
+    }
+  }
+}
+
+/// Moves a game's `meta.json` that can't be read into the quarantine folder,
+/// out of the way of fresh metadata, and records why. Nothing is deleted, the
+/// file can still be fixed by hand and put back.
+pub fn quarantine_game_meta(
+  game_id: &str,
+  json_meta_path: &Path,
+  err: &WadpunkError,
+) -> Result<(), WadpunkError> {
+  let quarantine_directory = database::get_quarantine_directory();
+  fs::create_dir_all(&quarantine_directory)?;
+
+  let now = Utc::now();
+  let quarantined_path = quarantine_directory.join(format!(
+    "{}.{}.meta.json",
+    database::normalize_name_from_id(game_id),
+    database::format_file_name_time(&now)
+  ));
+
+  fs::rename(json_meta_path, &quarantined_path)?;
+
+  record_issue(DbLibraryIssue {
+    game_id: Some(game_id.to_string()),
+    message: err.to_string(),
+    quarantined_path: Some(quarantined_path.to_str().unwrap().to_string()),
+    recorded_at: now.to_rfc3339(),
+  });
+
+  Ok(())
+}
+
+/// Records something that went wrong without stopping what was being done.
+/// Release builds have no console, this is how the user gets to see it.
+/// A warning repeats for as long as whatever causes it is still there, like
+/// on every refresh of the game list, it's only recorded the first time.
+pub fn record_warning(game_id: Option<&str>, message: String) {
+  let is_recorded = |x: &DbLibraryIssue| {
+    x.game_id.as_deref() == game_id && x.message == message && x.quarantined_path.is_none()
+  };
+
+  let mut db_library_issues = load_db_library_issues().unwrap_or_default();
+  db_library_issues
+    .issues
+    .extend(with_unrecorded_issues(|x| x.clone()));
+  if db_library_issues.issues.iter().any(is_recorded) {
+    return;
+  }
+
+  record_issue(DbLibraryIssue {
+    game_id: game_id.map(|x| x.to_string()),
+    message,
+    quarantined_path: None,
+    recorded_at: Utc::now().to_rfc3339(),
+  });
+}
+
+/// Issues that can't be written are kept in memory until they can, and are
+/// listed with the rest in the meantime.
+fn record_issue(db_library_issue: DbLibraryIssue) {
+  with_unrecorded_issues(|unrecorded_issues| {
+    unrecorded_issues.push(db_library_issue);
+
+    if write_issues(unrecorded_issues).is_ok() {
+      unrecorded_issues.clear();
+    }
+  })
+}
+
+fn with_unrecorded_issues<T, F>(f: F) -> T
+where
+  F: FnOnce(&mut Vec<DbLibraryIssue>) -> T,
+{
+  let mut unrecorded_issues = UNRECORDED_ISSUES.lock().unwrap();
+
+  f(unrecorded_issues
+    .entry(database::get_data_directory())
+    .or_default())
+}
+
+fn write_issues(new_issues: &[DbLibraryIssue]) -> Result<(), WadpunkError> {
+  let mut db_library_issues = load_db_library_issues()?;
+  db_library_issues.issues.extend_from_slice(new_issues);
+
+  let excess = db_library_issues.issues.len().saturating_sub(MAX_ISSUES);
+  db_library_issues.issues.drain(..excess);
+
+  let json_str = serde_json::to_string(&db_library_issues).unwrap();
+  fs::create_dir_all(database::get_quarantine_directory())?;
+  fs::write(get_issues_path(), json_str)?;
+
+  Ok(())
+}
+
+/// Everything that was repaired or went wrong, newest first.
+pub fn find_all_library_issues() -> Vec<LibraryIssue> {
+  let mut issues = match load_db_library_issues() {
+    Ok(db_library_issues) => db_library_issues.issues,
+    // The ones that couldn't be recorded are still worth seeing, with why.
+    Err(err) => vec![DbLibraryIssue {
+      game_id: None,
+      message: format!("could not read the library issues: {}", err),
+      quarantined_path: None,
+      recorded_at: Utc::now().to_rfc3339(),
+    }],
+  };
+  issues.extend(with_unrecorded_issues(|x| x.clone()));
+
+  issues.iter().rev().map(|x| x.to_library_issue()).collect()
+}
+
+fn load_db_library_issues() -> Result<DbLibraryIssues, WadpunkError> {
+  let issues_path = get_issues_path();
+  let json_contents = database::read_to_string_or_empty(&issues_path)?;
+
+  serde_json::from_str::<DbLibraryIssues>(&json_contents)
+    .map_err(|err| WadpunkError::parse(&issues_path, err))
+}
+
+fn get_issues_path() -> PathBuf {
+  database::get_quarantine_directory().join(ISSUES_FILE_NAME)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::library_index;
+
+  fn write_game_meta(name: &str, contents: &str) -> PathBuf {
+    let json_meta_path = database::get_meta_directory().join(name).join("meta.json");
+    fs::create_dir_all(json_meta_path.parent().unwrap()).unwrap();
+    fs::write(&json_meta_path, contents).unwrap();
+
+    json_meta_path
+  }
+
+  #[test]
+  fn quarantines_unparsable_game_meta() {
+    database::use_test_data_directory("quarantine-game-meta");
+    fs::create_dir_all(database::get_games_directory().join("mymap")).unwrap();
+    let json_meta_path = write_game_meta("mymap", r#"{"name":"#);
+
+    let db_game = database::load_game_meta("mymap/").unwrap();
+
+    assert_eq!(db_game.name.as_deref(), Some("mymap"));
+    let json_contents = fs::read_to_string(&json_meta_path).unwrap();
+    assert!(serde_json::from_str::<database::DbGameMeta>(&json_contents).is_ok());
+
+    let issues = find_all_library_issues();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].game_id.as_deref(), Some("mymap/"));
+    assert!(issues[0].message.contains(json_meta_path.to_str().unwrap()));
+
+    let quarantined_path = issues[0].quarantined_path.as_deref().unwrap();
+    assert!(Path::new(quarantined_path).starts_with(database::get_quarantine_directory()));
+    assert_eq!(fs::read_to_string(quarantined_path).unwrap(), r#"{"name":"#);
+  }
+
+  #[test]
+  fn quarantines_unparsable_external_game_meta() {
+    database::use_test_data_directory("quarantine-external-game-meta");
+    let json_meta_path = write_game_meta("mymap", r#"{"external_path":"#);
+    library_index::sync().unwrap();
+
+    let db_games = database::find_all_games().unwrap();
+
+    assert!(db_games.is_empty());
+    assert!(!json_meta_path.exists());
+
+    let issues = find_all_library_issues();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].game_id.as_deref(), Some("mymap"));
+    assert!(issues[0].quarantined_path.is_some());
+  }
+
+  #[test]
+  fn lists_issues_newest_first() {
+    database::use_test_data_directory("issues-newest-first");
+
+    record_warning(None, "first".to_string());
+    record_warning(Some("mymap/"), "second".to_string());
+
+    let issues = find_all_library_issues();
+    assert_eq!(issues.len(), 2);
+    assert_eq!(issues[0].message, "second");
+    assert_eq!(issues[0].game_id.as_deref(), Some("mymap/"));
+    assert_eq!(issues[0].quarantined_path, None);
+    assert_eq!(issues[1].message, "first");
+    assert_eq!(issues[1].game_id, None);
+  }
+
+  #[test]
+  fn records_repeated_warnings_once() {
+    database::use_test_data_directory("repeated-warnings");
+
+    for _ in 0..3 {
+      record_warning(Some("mymap/"), "could not read".to_string());
+    }
+    record_warning(Some("other/"), "could not read".to_string());
+
+    let issues = find_all_library_issues();
+    assert_eq!(issues.len(), 2);
+    assert_eq!(issues[0].game_id.as_deref(), Some("other/"));
+    assert_eq!(issues[1].game_id.as_deref(), Some("mymap/"));
+  }
+
+  #[test]
+  fn keeps_issues_that_cannot_be_written_with_their_library() {
+    database::use_test_data_directory("unwritable-issues-other");
+    database::use_test_data_directory("unwritable-issues");
+    fs::write(database::get_quarantine_directory(), "").unwrap();
+
+    record_warning(None, "could not read".to_string());
+
+    // Along with the issues file not being readable either.
+    let issues = find_all_library_issues();
+    assert_eq!(issues.len(), 2);
+    assert_eq!(issues[0].message, "could not read");
+
+    database::use_test_data_directory("unwritable-issues-other");
+    assert!(find_all_library_issues().is_empty());
+  }
+
+  #[test]
+  fn keeps_the_newest_issues() {
+    database::use_test_data_directory("newest-issues");
+
+    for i in 0..MAX_ISSUES + 5 {
+      record_warning(None, i.to_string());
+    }
+
+    let issues = find_all_library_issues();
+    assert_eq!(issues.len(), MAX_ISSUES);
+    assert_eq!(issues[0].message, (MAX_ISSUES + 4).to_string());
+    assert_eq!(issues[MAX_ISSUES - 1].message, "5");
+  }
+}
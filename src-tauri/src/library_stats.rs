@@ -0,0 +1,439 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use chrono::DateTime;
+use chrono::Datelike;
+use chrono::Local;
+
+use crate::database;
+use crate::database::DbGameMeta;
+use crate::error::WadpunkError;
+use crate::graphql::generated::GamePlayTime;
+use crate::graphql::generated::LibraryPlaySession;
+use crate::graphql::generated::PlayTimeGroup;
+use crate::library_store::LibraryStore;
+
+// What `getLibraryStats` loaded, by data directory, for its fields to use.
+// The generated `LibraryStats` only has room for the schema's own fields.
+static STATS_PLAY_SESSIONS: Mutex<BTreeMap<PathBuf, Arc<LibraryPlaySessions>>> =
+  Mutex::new(BTreeMap::new());
+
+pub struct LibraryPlaySessions {
+  pub db_games: Vec<DbGameMeta>,
+  pub entries: Vec<LibraryPlaySessionEntry>,
+}
+
+pub struct LibraryPlaySessionEntry {
+  pub game_id: String,
+  pub started_at: String,
+  pub ended_at: String,
+  pub started_at_local: DateTime<Local>,
+  pub duration: i64,
+  pub source_port: Option<String>,
+  pub iwad_id: Option<String>,
+  pub tags: Vec<String>,
+}
+
+pub enum StatsPeriod {
+  Day,
+  Week,
+  Month,
+}
+
+/// Every game in the library alongside every completed play session across
+/// all of them.
+pub fn load_library_play_sessions(
+  store: &dyn LibraryStore,
+) -> Result<(Vec<DbGameMeta>, Vec<LibraryPlaySessionEntry>), WadpunkError> {
+  let db_games = store.find_all_games()?;
+  let default_source_port_id = store
+    .find_all_source_ports()?
+    .into_iter()
+    .find(|x| x.is_default.unwrap_or_default())
+    .and_then(|x| x.id);
+
+  let mut entries: Vec<LibraryPlaySessionEntry> = vec![];
+
+  for db_game in &db_games {
+    let game_id = db_game.id.clone().unwrap();
+    let db_play_sessions = store.load_game_play_sessions_or_empty(&game_id);
+
+    // Sessions recorded before the source port / IWAD were tracked fall back
+    // to whatever the game is currently configured with.
+    let fallback_source_port = match db_game.source_port.as_deref() {
+      Some("-1") => default_source_port_id.clone(),
+      source_port => source_port.map(|x| x.to_string()),
+    };
+    let fallback_iwad_id = if db_game.is_iwad() {
+      Some(game_id.clone())
+    } else {
+      db_game.iwad_id.clone()
+    };
+
+    for play_session in db_play_sessions.sessions {
+      let Some(duration) = play_session.duration() else {
+        continue;
+      };
+      let started_at = play_session.started_at.unwrap();
+      let Ok(started_at_local) = DateTime::parse_from_rfc3339(&started_at) else {
+        continue;
+      };
+
+      entries.push(LibraryPlaySessionEntry {
+        game_id: game_id.clone(),
+        started_at,
+        ended_at: play_session.ended_at.unwrap(),
+        started_at_local: started_at_local.with_timezone(&Local),
+        duration,
+        source_port: play_session
+          .source_port
+          .or_else(|| fallback_source_port.clone()),
+        iwad_id: play_session.iwad_id.or_else(|| fallback_iwad_id.clone()),
+        tags: db_game.tags.clone().unwrap_or_default(),
+      });
+    }
+  }
+
+  Ok((db_games, entries))
+}
+
+/// Loads the library's play sessions once for `getLibraryStats`, and keeps
+/// them for the fields resolved after it.
+pub fn load_stats_play_sessions(
+  store: &dyn LibraryStore,
+) -> Result<Arc<LibraryPlaySessions>, WadpunkError> {
+  let (db_games, entries) = load_library_play_sessions(store)?;
+  let play_sessions = Arc::new(LibraryPlaySessions { db_games, entries });
+
+  STATS_PLAY_SESSIONS
+    .lock()
+    .unwrap()
+    .insert(database::get_data_directory(), play_sessions.clone());
+
+  Ok(play_sessions)
+}
+
+/// What `load_stats_play_sessions` kept, loaded now if it hasn't run yet.
+pub fn loaded_stats_play_sessions(
+  store: &dyn LibraryStore,
+) -> Result<Arc<LibraryPlaySessions>, WadpunkError> {
+  let play_sessions = STATS_PLAY_SESSIONS
+    .lock()
+    .unwrap()
+    .get(&database::get_data_directory())
+    .cloned();
+
+  match play_sessions {
+    Some(play_sessions) => Ok(play_sessions),
+    None => load_stats_play_sessions(store),
+  }
+}
+
+pub fn total_play_time(entries: &[LibraryPlaySessionEntry]) -> i64 {
+  entries.iter().map(|x| x.duration).sum()
+}
+
+pub fn group_by_period(
+  entries: &[LibraryPlaySessionEntry],
+  period: StatsPeriod,
+) -> Vec<PlayTimeGroup> {
+  group_by(entries, |entry| {
+    let date = entry.started_at_local.date_naive();
+
+    vec![match period {
+      StatsPeriod::Day => date.format("%Y-%m-%d").to_string(),
+      StatsPeriod::Week => {
+        let week = date.iso_week();
+        format!("{}-W{:02}", week.year(), week.week())
+      }
+      StatsPeriod::Month => date.format("%Y-%m").to_string(),
+    }]
+  })
+}
+
+pub fn group_by_source_port(entries: &[LibraryPlaySessionEntry]) -> Vec<PlayTimeGroup> {
+  group_by(entries, |entry| entry.source_port.iter().cloned().collect())
+}
+
+pub fn group_by_tag(entries: &[LibraryPlaySessionEntry]) -> Vec<PlayTimeGroup> {
+  group_by(entries, |entry| entry.tags.clone())
+}
+
+pub fn group_by_iwad(entries: &[LibraryPlaySessionEntry]) -> Vec<PlayTimeGroup> {
+  group_by(entries, |entry| entry.iwad_id.iter().cloned().collect())
+}
+
+pub fn longest_sessions(
+  entries: &[LibraryPlaySessionEntry],
+  limit: usize,
+) -> Vec<LibraryPlaySession> {
+  let mut sorted: Vec<&LibraryPlaySessionEntry> = entries.iter().collect();
+  sorted.sort_by_key(|x| Reverse(x.duration));
+
+  sorted
+    .into_iter()
+    .take(limit)
+    .map(|x| LibraryPlaySession {
+      game_id: x.game_id.clone(),
+      started_at: x.started_at.clone(),
+      ended_at: x.ended_at.clone(),
+      duration: x.duration.try_into().unwrap_or(i32::MAX),
+    })
+    .collect()
+}
+
+pub fn most_played_games(
+  db_games: &[DbGameMeta],
+  entries: &[LibraryPlaySessionEntry],
+  limit: usize,
+) -> Vec<GamePlayTime> {
+  let groups = group_by(entries, |entry| vec![entry.game_id.clone()]);
+  let mut game_play_times: Vec<GamePlayTime> = groups
+    .into_iter()
+    .map(|x| GamePlayTime {
+      name: db_games
+        .iter()
+        .find(|db_game| db_game.id.as_deref() == Some(x.key.as_str()))
+        .and_then(|db_game| db_game.name.clone())
+        .unwrap_or_else(|| database::normalize_name_from_id(&x.key).to_string()),
+      game_id: x.key,
+      duration: x.duration,
+      session_count: x.session_count,
+    })
+    .collect();
+
+  game_play_times.sort_by_key(|x| Reverse(x.duration));
+  game_play_times.truncate(limit);
+
+  game_play_times
+}
+
+pub fn never_launched_games<'a>(
+  db_games: &'a [DbGameMeta],
+  entries: &[LibraryPlaySessionEntry],
+) -> Vec<&'a DbGameMeta> {
+  db_games
+    .iter()
+    .filter(|db_game| {
+      !entries
+        .iter()
+        .any(|entry| db_game.id.as_deref() == Some(entry.game_id.as_str()))
+    })
+    .collect()
+}
+
+/// Sums play time and session counts under every key `get_keys` returns for a
+/// session. Groups are sorted by key.
+fn group_by<F>(entries: &[LibraryPlaySessionEntry], get_keys: F) -> Vec<PlayTimeGroup>
+where
+  F: Fn(&LibraryPlaySessionEntry) -> Vec<String>,
+{
+  let mut groups: HashMap<String, (i64, i64)> = HashMap::new();
+
+  for entry in entries {
+    for key in get_keys(entry) {
+      let group = groups.entry(key).or_default();
+      group.0 += entry.duration;
+      group.1 += 1;
+    }
+  }
+
+  let mut play_time_groups: Vec<PlayTimeGroup> = groups
+    .into_iter()
+    .map(|(key, (duration, session_count))| PlayTimeGroup {
+      key,
+      duration: duration.try_into().unwrap_or(i32::MAX),
+      session_count: session_count.try_into().unwrap_or(i32::MAX),
+    })
+    .collect();
+
+  play_time_groups.sort_by(|a, b| a.key.cmp(&b.key));
+
+  play_time_groups
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::TimeZone;
+
+  use super::*;
+  use crate::database::DbPlaySessionEntry;
+  use crate::library_store::MemoryLibraryStore;
+
+  fn entry(
+    game_id: &str,
+    (year, month, day): (i32, u32, u32),
+    duration: i64,
+  ) -> LibraryPlaySessionEntry {
+    let started_at_local = Local.with_ymd_and_hms(year, month, day, 12, 0, 0).unwrap();
+
+    LibraryPlaySessionEntry {
+      game_id: game_id.to_string(),
+      started_at: started_at_local.to_rfc3339(),
+      ended_at: (started_at_local + chrono::Duration::seconds(duration)).to_rfc3339(),
+      started_at_local,
+      duration,
+      source_port: None,
+      iwad_id: None,
+      tags: vec![],
+    }
+  }
+
+  fn to_tuples(play_time_groups: Vec<PlayTimeGroup>) -> Vec<(String, i32, i32)> {
+    play_time_groups
+      .into_iter()
+      .map(|x| (x.key, x.duration, x.session_count))
+      .collect()
+  }
+
+  fn test_entries() -> Vec<LibraryPlaySessionEntry> {
+    vec![
+      entry("mymap/", (2024, 3, 4), 60),
+      entry("mymap/", (2024, 3, 5), 30),
+      entry("other.wad", (2024, 3, 11), 10),
+      entry("other.wad", (2024, 4, 1), 5),
+    ]
+  }
+
+  #[test]
+  fn groups_by_day() {
+    assert_eq!(
+      to_tuples(group_by_period(&test_entries(), StatsPeriod::Day)),
+      vec![
+        ("2024-03-04".to_string(), 60, 1),
+        ("2024-03-05".to_string(), 30, 1),
+        ("2024-03-11".to_string(), 10, 1),
+        ("2024-04-01".to_string(), 5, 1),
+      ]
+    );
+  }
+
+  #[test]
+  fn groups_by_iso_week() {
+    let mut entries = test_entries();
+    // Belongs to the first week of 2025.
+    entries.push(entry("mymap/", (2024, 12, 30), 1));
+
+    assert_eq!(
+      to_tuples(group_by_period(&entries, StatsPeriod::Week)),
+      vec![
+        ("2024-W10".to_string(), 90, 2),
+        ("2024-W11".to_string(), 10, 1),
+        ("2024-W14".to_string(), 5, 1),
+        ("2025-W01".to_string(), 1, 1),
+      ]
+    );
+  }
+
+  #[test]
+  fn groups_by_month() {
+    assert_eq!(
+      to_tuples(group_by_period(&test_entries(), StatsPeriod::Month)),
+      vec![
+        ("2024-03".to_string(), 100, 3),
+        ("2024-04".to_string(), 5, 1)
+      ]
+    );
+  }
+
+  #[test]
+  fn leaves_sessions_without_a_source_port_or_iwad_out() {
+    let mut entries = test_entries();
+    entries[0].source_port = Some("gzdoom".to_string());
+    entries[1].source_port = Some("gzdoom".to_string());
+    entries[2].source_port = Some("dsda".to_string());
+    entries[0].iwad_id = Some("doom2.wad".to_string());
+
+    assert_eq!(
+      to_tuples(group_by_source_port(&entries)),
+      vec![("dsda".to_string(), 10, 1), ("gzdoom".to_string(), 90, 2)]
+    );
+    assert_eq!(
+      to_tuples(group_by_iwad(&entries)),
+      vec![("doom2.wad".to_string(), 60, 1)]
+    );
+  }
+
+  #[test]
+  fn counts_sessions_under_every_tag() {
+    let mut entries = test_entries();
+    entries[0].tags = vec!["slaughter".to_string(), "megawad".to_string()];
+    entries[2].tags = vec!["megawad".to_string()];
+
+    assert_eq!(
+      to_tuples(group_by_tag(&entries)),
+      vec![
+        ("megawad".to_string(), 70, 2),
+        ("slaughter".to_string(), 60, 1),
+      ]
+    );
+  }
+
+  #[test]
+  fn sorts_most_played_games_by_play_time() {
+    let mut db_game = database::default_game_meta("mymap/");
+    db_game.name = Some("My Map".to_string());
+
+    let game_play_times = most_played_games(&[db_game], &test_entries(), 1);
+
+    assert_eq!(game_play_times.len(), 1);
+    assert_eq!(game_play_times[0].game_id, "mymap/");
+    assert_eq!(game_play_times[0].name, "My Map");
+    assert_eq!(game_play_times[0].duration, 90);
+    assert_eq!(game_play_times[0].session_count, 2);
+
+    // Games that aren't in the library anymore go by their id.
+    let game_play_times = most_played_games(&[], &test_entries(), 2);
+    assert_eq!(game_play_times[1].name, "other.wad");
+  }
+
+  #[test]
+  fn finds_longest_sessions_and_never_launched_games() {
+    let entries = test_entries();
+
+    let longest = longest_sessions(&entries, 2);
+    assert_eq!(
+      longest.iter().map(|x| x.duration).collect::<Vec<_>>(),
+      vec![60, 30]
+    );
+    assert_eq!(total_play_time(&entries), 105);
+
+    let db_games = vec![
+      database::default_game_meta("mymap/"),
+      database::default_game_meta("unplayed.wad"),
+    ];
+    let never_launched = never_launched_games(&db_games, &entries);
+    assert_eq!(never_launched.len(), 1);
+    assert_eq!(never_launched[0].id.as_deref(), Some("unplayed.wad"));
+  }
+
+  #[test]
+  fn shares_the_play_sessions_loaded_for_stats() {
+    database::use_test_data_directory("stats-play-sessions");
+    let store = MemoryLibraryStore::default();
+    store.load_game_meta("mymap.wad").unwrap();
+
+    let play_session = DbPlaySessionEntry {
+      started_at: Some("2024-03-04T12:00:00+00:00".to_string()),
+      ended_at: Some("2024-03-04T12:01:00+00:00".to_string()),
+      source_port: None,
+      iwad_id: None,
+    };
+    store
+      .record_game_play_session("mymap.wad", play_session.clone())
+      .unwrap();
+
+    assert_eq!(load_stats_play_sessions(&store).unwrap().entries.len(), 1);
+
+    // Fields resolved after `getLibraryStats` don't load them again.
+    store
+      .record_game_play_session("mymap.wad", play_session)
+      .unwrap();
+    assert_eq!(loaded_stats_play_sessions(&store).unwrap().entries.len(), 1);
+    assert_eq!(load_stats_play_sessions(&store).unwrap().entries.len(), 2);
+  }
+}
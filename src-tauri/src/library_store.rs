@@ -0,0 +1,443 @@
+#[cfg(test)]
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+#[cfg(test)]
+use std::sync::Mutex;
+
+use crate::database;
+use crate::database::DbGameMeta;
+use crate::database::DbPlaySession;
+use crate::database::DbPlaySessionEntry;
+use crate::database::DbSourcePort;
+use crate::database::GameFile;
+use crate::error::WadpunkError;
+use crate::library_issues;
+
+/// What the resolvers get from the GraphQL context.
+pub type SharedLibraryStore = Arc<dyn LibraryStore>;
+
+/// Where game metadata, play sessions and source ports are kept. Game files
+/// themselves always live on disk, see `get_game_path`.
+pub trait LibraryStore: Send + Sync {
+  fn find_all_games(&self) -> Result<Vec<DbGameMeta>, WadpunkError>;
+
+  /// Creates the metadata when the game doesn't have any yet.
+  fn load_game_meta(&self, game_id: &str) -> Result<DbGameMeta, WadpunkError>;
+
+  /// Same as `load_game_meta`, for games that have to be in the library
+  /// already.
+  fn find_game_by_id(&self, id: &str) -> Result<DbGameMeta, WadpunkError>;
+
+  fn save_game(&self, db_game: DbGameMeta) -> Result<(), WadpunkError>;
+
+  /// Where an external game's files are, `None` for games in the games
+  /// directory and games that aren't in the library. Doesn't create the
+  /// metadata when there is none.
+  fn find_external_path(&self, game_id: &str) -> Option<String>;
+
+  /// Where a game's files are. That's the games directory, unless the game
+  /// was referenced from somewhere else.
+  fn get_game_path(&self, game_id: &str) -> PathBuf {
+    self
+      .find_external_path(game_id)
+      .map(PathBuf::from)
+      .unwrap_or_else(|| database::get_games_directory().join(game_id))
+  }
+
+  /// Whether the game is in the library. External games count even when
+  /// their drive isn't connected.
+  fn game_exists(&self, game_id: &str) -> bool {
+    database::get_games_directory().join(game_id).exists()
+      || self.find_external_path(game_id).is_some()
+  }
+
+  fn find_all_game_files(&self, game_id: &str) -> Vec<GameFile> {
+    let archive_entries = if game_id.ends_with("/") {
+      vec![]
+    } else {
+      self
+        .load_game_meta(game_id)
+        .ok()
+        .and_then(|x| x.archive_entries)
+        .unwrap_or_default()
+    };
+
+    database::find_game_files(game_id, &self.get_game_path(game_id), archive_entries)
+  }
+
+  fn load_game_play_sessions(&self, game_id: &str) -> Result<DbPlaySession, WadpunkError>;
+
+  /// For views of the whole library, which one unreadable file shouldn't
+  /// take down. The game counts as never played, and the issue is recorded.
+  fn load_game_play_sessions_or_empty(&self, game_id: &str) -> DbPlaySession {
+    self.load_game_play_sessions(game_id).unwrap_or_else(|err| {
+      library_issues::record_warning(
+        Some(game_id),
+        format!("could not read the play sessions: {}", err),
+      );
+
+      DbPlaySession::default()
+    })
+  }
+
+  fn record_game_play_session(
+    &self,
+    game_id: &str,
+    db_play_session_entry: DbPlaySessionEntry,
+  ) -> Result<(), WadpunkError>;
+
+  fn find_all_source_ports(&self) -> Result<Vec<DbSourcePort>, WadpunkError>;
+
+  fn find_source_port_by_id(&self, source_port_id: &str) -> Result<DbSourcePort, WadpunkError>;
+
+  fn save_source_port(&self, db_source_port: DbSourcePort) -> Result<(), WadpunkError>;
+
+  fn delete_source_port(&self, id: &str) -> Result<(), WadpunkError>;
+
+  /// Makes `id` the only default source port, when `is_default` is set.
+  fn set_default_source_port(
+    &self,
+    id: &str,
+    is_default: Option<bool>,
+  ) -> Result<(), WadpunkError> {
+    if is_default != Some(true) {
+      return Ok(());
+    }
+
+    for mut source_port in self.find_all_source_ports()? {
+      source_port.is_default = Some(source_port.id.as_deref() == Some(id));
+
+      self.save_source_port(source_port)?;
+    }
+
+    Ok(())
+  }
+}
+
+/// The JSON files under the data directory, indexed by `library_index.rs`.
+pub struct JsonLibraryStore;
+
+impl LibraryStore for JsonLibraryStore {
+  fn find_all_games(&self) -> Result<Vec<DbGameMeta>, WadpunkError> {
+    database::find_all_games()
+  }
+
+  fn load_game_meta(&self, game_id: &str) -> Result<DbGameMeta, WadpunkError> {
+    database::load_game_meta(game_id)
+  }
+
+  fn find_game_by_id(&self, id: &str) -> Result<DbGameMeta, WadpunkError> {
+    if !self.game_exists(id) {
+      return Err(WadpunkError::NotFound(format!("game {}", id)));
+    }
+
+    database::load_game_meta(id)
+  }
+
+  fn save_game(&self, db_game: DbGameMeta) -> Result<(), WadpunkError> {
+    database::save_game(db_game)
+  }
+
+  fn find_external_path(&self, game_id: &str) -> Option<String> {
+    database::find_external_path(game_id)
+  }
+
+  fn load_game_play_sessions(&self, game_id: &str) -> Result<DbPlaySession, WadpunkError> {
+    database::load_game_play_sessions(game_id)
+  }
+
+  fn record_game_play_session(
+    &self,
+    game_id: &str,
+    db_play_session_entry: DbPlaySessionEntry,
+  ) -> Result<(), WadpunkError> {
+    database::record_game_play_session(game_id, db_play_session_entry)
+  }
+
+  fn find_all_source_ports(&self) -> Result<Vec<DbSourcePort>, WadpunkError> {
+    database::find_all_source_ports()
+  }
+
+  fn find_source_port_by_id(&self, source_port_id: &str) -> Result<DbSourcePort, WadpunkError> {
+    database::find_source_port_by_id(source_port_id)
+  }
+
+  fn save_source_port(&self, db_source_port: DbSourcePort) -> Result<(), WadpunkError> {
+    database::save_source_port(db_source_port)
+  }
+
+  fn delete_source_port(&self, id: &str) -> Result<(), WadpunkError> {
+    database::delete_source_port(id)
+  }
+}
+
+/// Keeps everything in memory and nothing on disk, for tests. Games only
+/// exist once they've been saved or loaded.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryLibraryStore {
+  games: Mutex<BTreeMap<String, DbGameMeta>>,
+  play_sessions: Mutex<BTreeMap<String, DbPlaySession>>,
+  source_ports: Mutex<BTreeMap<String, DbSourcePort>>,
+}
+
+#[cfg(test)]
+impl LibraryStore for MemoryLibraryStore {
+  fn find_all_games(&self) -> Result<Vec<DbGameMeta>, WadpunkError> {
+    Ok(self.games.lock().unwrap().values().cloned().collect())
+  }
+
+  fn load_game_meta(&self, game_id: &str) -> Result<DbGameMeta, WadpunkError> {
+    let mut games = self.games.lock().unwrap();
+
+    let db_game = games
+      .entry(game_id.to_string())
+      .or_insert_with(|| database::default_game_meta(game_id));
+
+    Ok(db_game.clone())
+  }
+
+  fn find_game_by_id(&self, id: &str) -> Result<DbGameMeta, WadpunkError> {
+    let games = self.games.lock().unwrap();
+
+    games
+      .get(id)
+      .cloned()
+      .ok_or_else(|| WadpunkError::NotFound(format!("game {}", id)))
+  }
+
+  fn save_game(&self, db_game: DbGameMeta) -> Result<(), WadpunkError> {
+    let game_id = db_game
+      .id
+      .clone()
+      .ok_or_else(|| WadpunkError::InvalidInput("game has no id".to_string()))?;
+
+    self.games.lock().unwrap().insert(game_id, db_game);
+
+    Ok(())
+  }
+
+  /// Games are looked up by name, with or without the `/` folder games end
+  /// in, like the metadata folders on disk.
+  fn find_external_path(&self, game_id: &str) -> Option<String> {
+    let name = database::normalize_name_from_id(game_id);
+    let games = self.games.lock().unwrap();
+
+    games
+      .iter()
+      .find(|(id, _)| database::normalize_name_from_id(id) == name)
+      .and_then(|(_, db_game)| db_game.external_path.clone())
+  }
+
+  fn load_game_play_sessions(&self, game_id: &str) -> Result<DbPlaySession, WadpunkError> {
+    let play_sessions = self.play_sessions.lock().unwrap();
+
+    Ok(play_sessions.get(game_id).cloned().unwrap_or_default())
+  }
+
+  fn record_game_play_session(
+    &self,
+    game_id: &str,
+    db_play_session_entry: DbPlaySessionEntry,
+  ) -> Result<(), WadpunkError> {
+    let mut play_sessions = self.play_sessions.lock().unwrap();
+    let db_play_sessions = play_sessions.entry(game_id.to_string()).or_default();
+
+    db_play_sessions.sessions.push(db_play_session_entry);
+
+    Ok(())
+  }
+
+  fn find_all_source_ports(&self) -> Result<Vec<DbSourcePort>, WadpunkError> {
+    let source_ports = self.source_ports.lock().unwrap();
+
+    Ok(source_ports.values().cloned().collect())
+  }
+
+  fn find_source_port_by_id(&self, source_port_id: &str) -> Result<DbSourcePort, WadpunkError> {
+    let source_ports = self.source_ports.lock().unwrap();
+
+    source_ports
+      .get(source_port_id)
+      .cloned()
+      .ok_or_else(|| WadpunkError::NotFound(format!("source port {}", source_port_id)))
+  }
+
+  fn save_source_port(&self, db_source_port: DbSourcePort) -> Result<(), WadpunkError> {
+    let id = db_source_port
+      .id
+      .clone()
+      .ok_or_else(|| WadpunkError::InvalidInput("source port has no id".to_string()))?;
+
+    self.source_ports.lock().unwrap().insert(id, db_source_port);
+
+    Ok(())
+  }
+
+  fn delete_source_port(&self, id: &str) -> Result<(), WadpunkError> {
+    self
+      .source_ports
+      .lock()
+      .unwrap()
+      .remove(id)
+      .map(|_| ())
+      .ok_or_else(|| WadpunkError::NotFound(format!("source port {}", id)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::graphql::generated::GameStatus;
+  use crate::library_stats;
+
+  fn play_session(source_port: Option<&str>, iwad_id: Option<&str>) -> DbPlaySessionEntry {
+    DbPlaySessionEntry {
+      started_at: Some("2024-03-04T12:00:00+00:00".to_string()),
+      ended_at: Some("2024-03-04T12:01:00+00:00".to_string()),
+      source_port: source_port.map(|x| x.to_string()),
+      iwad_id: iwad_id.map(|x| x.to_string()),
+    }
+  }
+
+  fn source_port(id: &str, is_default: bool) -> DbSourcePort {
+    DbSourcePort {
+      schema_version: 0,
+      id: Some(id.to_string()),
+      command: Some(vec![id.to_string()]),
+      known_source_port_id: "gzdoom".to_string(),
+      is_default: Some(is_default),
+    }
+  }
+
+  #[test]
+  fn resolves_status_from_play_sessions() {
+    let store = MemoryLibraryStore::default();
+    let db_game = store.load_game_meta("mymap.wad").unwrap();
+
+    assert_eq!(db_game.resolve_status(&store), GameStatus::UNPLAYED);
+
+    store
+      .record_game_play_session("mymap.wad", play_session(None, None))
+      .unwrap();
+    assert_eq!(db_game.resolve_status(&store), GameStatus::ABANDONED);
+
+    let mut recent_play_session = play_session(None, None);
+    recent_play_session.started_at = Some(chrono::Utc::now().to_rfc3339());
+    store
+      .record_game_play_session("mymap.wad", recent_play_session)
+      .unwrap();
+    assert_eq!(db_game.resolve_status(&store), GameStatus::PLAYING);
+
+    // Set by hand, that wins over play sessions.
+    let mut db_game = db_game;
+    db_game.status = Some(GameStatus::BEATEN);
+    assert_eq!(db_game.to_game(&store).unwrap().status, GameStatus::BEATEN);
+  }
+
+  #[test]
+  fn falls_back_to_the_current_settings_for_old_play_sessions() {
+    let store = MemoryLibraryStore::default();
+    store.save_source_port(source_port("a", false)).unwrap();
+    store.save_source_port(source_port("b", true)).unwrap();
+
+    let mut db_game = store.load_game_meta("mymap.wad").unwrap();
+    db_game.source_port = Some("-1".to_string());
+    db_game.iwad_id = Some("doom2.wad".to_string());
+    db_game.tags = Some(vec!["megawad".to_string()]);
+    store.save_game(db_game).unwrap();
+
+    store
+      .record_game_play_session("mymap.wad", play_session(None, None))
+      .unwrap();
+    store
+      .record_game_play_session("mymap.wad", play_session(Some("a"), Some("tnt.wad")))
+      .unwrap();
+    // Never ended, so it doesn't count.
+    store
+      .record_game_play_session(
+        "mymap.wad",
+        DbPlaySessionEntry {
+          ended_at: None,
+          ..play_session(None, None)
+        },
+      )
+      .unwrap();
+
+    let (db_games, entries) = library_stats::load_library_play_sessions(&store).unwrap();
+
+    assert_eq!(db_games.len(), 1);
+    assert_eq!(
+      entries
+        .iter()
+        .map(|x| (x.source_port.as_deref(), x.iwad_id.as_deref(), x.duration))
+        .collect::<Vec<_>>(),
+      vec![
+        (Some("b"), Some("doom2.wad"), 60),
+        (Some("a"), Some("tnt.wad"), 60),
+      ]
+    );
+    assert_eq!(entries[0].tags, vec!["megawad".to_string()]);
+  }
+
+  #[test]
+  fn makes_one_source_port_the_default() {
+    let store = MemoryLibraryStore::default();
+    store.save_source_port(source_port("a", true)).unwrap();
+    store.save_source_port(source_port("b", false)).unwrap();
+    store.save_source_port(source_port("c", false)).unwrap();
+
+    let find_defaults = || {
+      store
+        .find_all_source_ports()
+        .unwrap()
+        .into_iter()
+        .filter(|x| x.is_default == Some(true))
+        .map(|x| x.id.unwrap())
+        .collect::<Vec<_>>()
+    };
+
+    // Only setting it as the default changes anything.
+    store.set_default_source_port("b", None).unwrap();
+    store.set_default_source_port("b", Some(false)).unwrap();
+    assert_eq!(find_defaults(), vec!["a".to_string()]);
+
+    store.set_default_source_port("b", Some(true)).unwrap();
+    assert_eq!(find_defaults(), vec!["b".to_string()]);
+  }
+
+  #[test]
+  fn finds_external_games_in_the_store() {
+    let data_directory = database::use_test_data_directory("memory-external-game");
+    let external_path = data_directory.join("elsewhere").join("mymap");
+    std::fs::create_dir_all(&external_path).unwrap();
+    std::fs::write(external_path.join("mymap.wad"), "").unwrap();
+
+    let store = MemoryLibraryStore::default();
+    assert!(!store.game_exists("mymap/"));
+
+    let mut db_game = store.load_game_meta("mymap/").unwrap();
+    db_game.external_path = Some(external_path.to_str().unwrap().to_string());
+    store.save_game(db_game).unwrap();
+
+    assert!(store.game_exists("mymap/"));
+    assert!(store.find_game_by_id("mymap/").is_ok());
+    assert_eq!(store.get_game_path("mymap/"), external_path);
+    assert_eq!(
+      store.find_all_game_files("mymap/"),
+      vec![GameFile {
+        path: external_path
+          .join("mymap.wad")
+          .to_str()
+          .unwrap()
+          .to_string(),
+        archive_entries: vec![],
+      }]
+    );
+    // Without the `/`, the way imports check whether a name is taken.
+    assert!(store.find_external_path("mymap").is_some());
+    // Nothing was read from or written to the metadata on disk.
+    assert!(!database::get_meta_directory().join("mymap").exists());
+  }
+}
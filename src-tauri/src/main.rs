@@ -2,26 +2,53 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use std::sync::Arc;
+
 use graphql::datasource::DataSource;
+use library_store::JsonLibraryStore;
+use library_store::SharedLibraryStore;
 
 mod database;
+mod downloader;
+mod error;
+mod file_types;
 mod graphql;
+mod idgames;
+mod idgames_index;
+mod idgames_text;
 mod importer;
+mod inbox_watcher;
+mod iwad_inference;
 mod known_source_ports;
+mod library_index;
+mod library_issues;
+mod library_stats;
+mod library_store;
+mod migrations;
+mod saves;
+mod store_installs;
 mod tauri_helpers;
 
 fn main() {
+  let library_store: SharedLibraryStore = Arc::new(JsonLibraryStore);
+
   let schema = async_graphql::Schema::build(
     graphql::generated::Query,
     graphql::generated::Mutation,
     async_graphql::EmptySubscription,
   )
   .data(DataSource)
+  .data(library_store.clone())
   .finish();
 
   tauri::Builder::default()
     .plugin(tauri_plugin_graphql::init(schema))
     .plugin(tauri_plugin_window_state::Builder::default().build())
+    .setup(move |app| {
+      inbox_watcher::start(app.handle(), library_store);
+
+      Ok(())
+    })
     .run(tauri::generate_context!())
     .expect("error while running tauri application");
 }
@@ -0,0 +1,291 @@
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use serde_json::Map;
+use serde_json::Value;
+
+use crate::error::WadpunkError;
+
+/// Upgrades a file from the version it's at, its index in the list, to the
+/// next one. Once a migration has shipped it's never changed, only new ones
+/// are added after it.
+type Migration = fn(&mut Map<String, Value>);
+
+const GAME_META_MIGRATIONS: [Migration; 1] = [game_meta_v1];
+const PLAY_SESSIONS_MIGRATIONS: [Migration; 1] = [play_sessions_v1];
+const SOURCE_PORT_MIGRATIONS: [Migration; 1] = [source_port_v1];
+
+// Written into every file when it's saved. Files from before versioning
+// don't have a `schema_version`, they're version 0.
+pub const GAME_META_SCHEMA_VERSION: u32 = GAME_META_MIGRATIONS.len() as u32;
+pub const PLAY_SESSIONS_SCHEMA_VERSION: u32 = PLAY_SESSIONS_MIGRATIONS.len() as u32;
+pub const SOURCE_PORT_SCHEMA_VERSION: u32 = SOURCE_PORT_MIGRATIONS.len() as u32;
+
+#[derive(Clone, Copy)]
+pub enum Schema {
+  GameMeta,
+  PlaySessions,
+  SourcePort,
+}
+
+impl Schema {
+  fn migrations(&self) -> &'static [Migration] {
+    match self {
+      Self::GameMeta => &GAME_META_MIGRATIONS,
+      Self::PlaySessions => &PLAY_SESSIONS_MIGRATIONS,
+      Self::SourcePort => &SOURCE_PORT_MIGRATIONS,
+    }
+  }
+}
+
+/// Brings the contents of a file written by an older version up to the
+/// current schema, saving the upgraded file over it. The original is kept as
+/// `<file>.v<version>.bak`. Up to date files, and files from a newer version,
+/// are returned as they are. Saving a newer file drops what this version
+/// doesn't know about, so it gets the same backup first.
+///
+/// Only the library sync calls this, reading a file never writes to it, see
+/// `upgrade`.
+pub fn migrate(path: &Path, schema: Schema, contents: &str) -> Result<String, WadpunkError> {
+  let (version, upgraded) = apply_migrations(path, schema, contents)?;
+
+  if version > schema.migrations().len() {
+    write_backup(path, version, contents)?;
+  }
+  let Some(json_str) = upgraded else {
+    return Ok(contents.to_string());
+  };
+
+  write_backup(path, version, contents)?;
+  fs::write(path, &json_str)?;
+
+  Ok(json_str)
+}
+
+/// Like `migrate`, but the upgrade only happens in memory. Used when reading
+/// files that changed since the library was synced.
+pub fn upgrade(path: &Path, schema: Schema, contents: &str) -> Result<String, WadpunkError> {
+  let (_, upgraded) = apply_migrations(path, schema, contents)?;
+
+  Ok(upgraded.unwrap_or_else(|| contents.to_string()))
+}
+
+/// The version a file is at, and its upgraded contents if it's older than
+/// the current schema.
+fn apply_migrations(
+  path: &Path,
+  schema: Schema,
+  contents: &str,
+) -> Result<(usize, Option<String>), WadpunkError> {
+  let mut value =
+    serde_json::from_str::<Value>(contents).map_err(|err| WadpunkError::parse(path, err))?;
+
+  // Not a file any version wrote, deserializing it will say what's wrong.
+  let Some(object) = value.as_object_mut() else {
+    return Ok((0, None));
+  };
+
+  let migrations = schema.migrations();
+  let version = object
+    .get("schema_version")
+    .and_then(|x| x.as_u64())
+    .unwrap_or_default() as usize;
+
+  if version >= migrations.len() {
+    return Ok((version, None));
+  }
+
+  for migration in &migrations[version..] {
+    migration(object);
+  }
+  object.insert("schema_version".to_string(), migrations.len().into());
+
+  Ok((version, Some(serde_json::to_string(&value).unwrap())))
+}
+
+/// A backup left by an upgrade that didn't get to save is the real original,
+/// it's never overwritten.
+fn write_backup(path: &Path, version: usize, contents: &str) -> Result<(), WadpunkError> {
+  let backup_path = get_backup_path(path, version);
+  if !backup_path.exists() {
+    fs::write(&backup_path, contents)?;
+  }
+
+  Ok(())
+}
+
+fn get_backup_path(path: &Path, version: usize) -> PathBuf {
+  let file_name = path.file_name().unwrap().to_str().unwrap();
+
+  path.with_file_name(format!("{}.v{}.bak", file_name, version))
+}
+
+/// Games imported before `installedAt` was recorded sort as the oldest.
+fn game_meta_v1(object: &mut Map<String, Value>) {
+  if !object.get("installed_at").is_some_and(|x| x.is_string()) {
+    object.insert(
+      "installed_at".to_string(),
+      "1970-01-01T00:00:00+00:00".into(),
+    );
+  }
+}
+
+fn play_sessions_v1(object: &mut Map<String, Value>) {
+  if !object.get("sessions").is_some_and(|x| x.is_array()) {
+    object.insert("sessions".to_string(), Value::Array(vec![]));
+  }
+}
+
+/// Source ports were GZDoom until the known source port could be picked.
+fn source_port_v1(object: &mut Map<String, Value>) {
+  if !object
+    .get("known_source_port_id")
+    .is_some_and(|x| x.is_string())
+  {
+    object.insert("known_source_port_id".to_string(), "gzdoom".into());
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn write_test_file(test_name: &str, contents: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!(
+      "wadpunk-migrations-{}-{}",
+      test_name,
+      std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+
+    let path = directory.join("meta.json");
+    fs::write(&path, contents).unwrap();
+
+    path
+  }
+
+  fn migrate_test_file(path: &Path, schema: Schema) -> Value {
+    let contents = fs::read_to_string(path).unwrap();
+
+    serde_json::from_str(&migrate(path, schema, &contents).unwrap()).unwrap()
+  }
+
+  #[test]
+  fn migrates_files_from_before_versioning() {
+    let original = r#"{"name":"mymap"}"#;
+    let path = write_test_file("before-versioning", original);
+
+    let value = migrate_test_file(&path, Schema::GameMeta);
+
+    assert_eq!(value["schema_version"], GAME_META_SCHEMA_VERSION);
+    assert_eq!(value["installed_at"], "1970-01-01T00:00:00+00:00");
+    assert_eq!(value["name"], "mymap");
+    assert_eq!(
+      serde_json::from_str::<Value>(&fs::read_to_string(&path).unwrap()).unwrap(),
+      value
+    );
+    assert_eq!(
+      fs::read_to_string(get_backup_path(&path, 0)).unwrap(),
+      original
+    );
+  }
+
+  #[test]
+  fn keeps_values_the_file_already_has() {
+    let path = write_test_file(
+      "existing-values",
+      r#"{"installed_at":"2024-01-02T03:04:05+00:00"}"#,
+    );
+
+    let value = migrate_test_file(&path, Schema::GameMeta);
+
+    assert_eq!(value["installed_at"], "2024-01-02T03:04:05+00:00");
+  }
+
+  #[test]
+  fn fills_in_play_sessions_and_source_ports() {
+    let play_sessions_path = write_test_file("play-sessions", "{}");
+    let source_port_path = write_test_file("source-port", r#"{"id":"a"}"#);
+
+    let play_sessions = migrate_test_file(&play_sessions_path, Schema::PlaySessions);
+    let source_port = migrate_test_file(&source_port_path, Schema::SourcePort);
+
+    assert_eq!(play_sessions["sessions"], Value::Array(vec![]));
+    assert_eq!(
+      play_sessions["schema_version"],
+      PLAY_SESSIONS_SCHEMA_VERSION
+    );
+    assert_eq!(source_port["known_source_port_id"], "gzdoom");
+    assert_eq!(source_port["schema_version"], SOURCE_PORT_SCHEMA_VERSION);
+  }
+
+  #[test]
+  fn leaves_up_to_date_files_alone() {
+    let contents = format!(
+      r#"{{"schema_version":{},"installed_at":"x"}}"#,
+      GAME_META_SCHEMA_VERSION
+    );
+    let path = write_test_file("up-to-date", &contents);
+
+    assert_eq!(
+      migrate(&path, Schema::GameMeta, &contents).unwrap(),
+      contents
+    );
+    assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+  }
+
+  #[test]
+  fn backs_up_files_from_a_newer_version() {
+    let contents = r#"{"schema_version":99,"from_the_future":true}"#;
+    let path = write_test_file("newer-version", contents);
+
+    assert_eq!(
+      migrate(&path, Schema::GameMeta, contents).unwrap(),
+      contents
+    );
+    assert_eq!(fs::read_to_string(&path).unwrap(), contents);
+    assert_eq!(
+      fs::read_to_string(get_backup_path(&path, 99)).unwrap(),
+      contents
+    );
+  }
+
+  #[test]
+  fn keeps_the_first_backup() {
+    let path = write_test_file("first-backup", "{}");
+    fs::write(get_backup_path(&path, 0), "original").unwrap();
+
+    migrate_test_file(&path, Schema::GameMeta);
+
+    assert_eq!(
+      fs::read_to_string(get_backup_path(&path, 0)).unwrap(),
+      "original"
+    );
+  }
+
+  #[test]
+  fn upgrades_in_memory_without_writing() {
+    let original = r#"{"name":"mymap"}"#;
+    let path = write_test_file("in-memory", original);
+
+    let value: Value =
+      serde_json::from_str(&upgrade(&path, Schema::GameMeta, original).unwrap()).unwrap();
+
+    assert_eq!(value["schema_version"], GAME_META_SCHEMA_VERSION);
+    assert_eq!(fs::read_to_string(&path).unwrap(), original);
+    assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+  }
+
+  #[test]
+  fn fails_on_files_that_are_not_json() {
+    let path = write_test_file("not-json", "{");
+
+    assert!(matches!(
+      migrate(&path, Schema::GameMeta, "{"),
+      Err(WadpunkError::Parse { .. })
+    ));
+    assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+  }
+}
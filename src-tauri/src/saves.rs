@@ -0,0 +1,590 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Deserialize;
+use tauri::api::dir::read_dir;
+
+use crate::database;
+use crate::error::WadpunkError;
+use crate::graphql::generated::SaveGame;
+use crate::graphql::generated::SaveSnapshot;
+use crate::known_source_ports::get_all_known_source_ports;
+use crate::library_store::LibraryStore;
+
+// Doom and most of its descendants start `.dsg` files with a fixed-size,
+// null-padded description.
+const DSG_DESCRIPTION_SIZE: usize = 24;
+
+// PrBoom+ and DSDA follow the description with a version string and a lump
+// checksum, then list the loaded WADs.
+const DSG_WAD_LIST_OFFSET: usize = DSG_DESCRIPTION_SIZE + 16 + 8;
+
+const SAVE_EXTENSIONS: [&str; 2] = ["zds", "dsg"];
+
+// Source port directories can hold a lot more than saves, this keeps scanning
+// them quick.
+const SAVE_SCAN_DEPTH: usize = 4;
+
+// Folders next to a portable port's executable that saves go in, lowercase.
+const PORTABLE_SAVE_FOLDERS: [&str; 3] = ["save", "saves", "savegames"];
+
+// Older snapshots are deleted once a game has more than this.
+const MAX_SAVE_SNAPSHOTS: usize = 10;
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ZdsInfo {
+  #[serde(rename = "Title")]
+  pub title: Option<String>,
+  #[serde(rename = "Current Map")]
+  pub current_map: Option<String>,
+  #[serde(rename = "Game WAD")]
+  pub game_wad: Option<String>,
+  #[serde(rename = "Map WAD")]
+  pub map_wad: Option<String>,
+  #[serde(rename = "Comment")]
+  pub comment: Option<String>,
+}
+
+pub fn find_all_game_saves(game_id: &str) -> Vec<SaveGame> {
+  let saves_directory = database::get_game_saves_directory(game_id);
+
+  if !saves_directory.exists() {
+    return vec![];
+  }
+
+  let mut files: Vec<String> = vec![];
+  for disk_entry in read_dir(saves_directory, true).unwrap_or_default() {
+    database::recurse_disk_entry(disk_entry, &mut files);
+  }
+
+  let mut saves: Vec<SaveGame> = files
+    .iter()
+    .map(|file| load_save_game(Path::new(file)))
+    .collect();
+
+  saves.sort_by(|a, b| b.modified_at.cmp(&a.modified_at));
+
+  saves
+}
+
+pub fn load_save_game(path: &Path) -> SaveGame {
+  let modified_at: DateTime<Utc> = fs::metadata(path)
+    .and_then(|x| x.modified())
+    .map(|x| x.into())
+    .unwrap_or_default();
+
+  let mut save_game = SaveGame {
+    absolute: path.to_string_lossy().to_string(),
+    file_name: path
+      .file_name()
+      .map(|x| x.to_string_lossy().to_string())
+      .unwrap_or_default(),
+    modified_at: modified_at.to_rfc3339(),
+    title: None,
+    map: None,
+    play_time: None,
+    game_wad: None,
+    map_wad: None,
+    thumbnail: None,
+  };
+
+  let extension = path
+    .extension()
+    .and_then(|x| x.to_str())
+    .unwrap_or_default()
+    .to_lowercase();
+
+  if extension == "zds" {
+    if let Some(info) = read_zds_info(path) {
+      save_game.title = info.title;
+      save_game.map = info.current_map;
+      save_game.game_wad = info.game_wad;
+      save_game.map_wad = info.map_wad;
+      save_game.play_time = info.comment.as_deref().and_then(parse_zds_play_time);
+    }
+
+    save_game.thumbnail = read_zds_thumbnail(path);
+  } else if extension == "dsg" {
+    save_game.title = read_dsg_description(path);
+  }
+
+  save_game
+}
+
+/// GZDoom saves are zip archives with an `info.json` describing the save.
+pub fn read_zds_info(path: &Path) -> Option<ZdsInfo> {
+  let mut archive = zip::ZipArchive::new(File::open(path).ok()?).ok()?;
+  let mut json_contents = String::new();
+  archive
+    .by_name("info.json")
+    .ok()?
+    .read_to_string(&mut json_contents)
+    .ok()?;
+
+  serde_json::from_str::<ZdsInfo>(&json_contents).ok()
+}
+
+fn read_zds_thumbnail(path: &Path) -> Option<String> {
+  let mut archive = zip::ZipArchive::new(File::open(path).ok()?).ok()?;
+  let mut png = vec![];
+  archive
+    .by_name("savepic.png")
+    .ok()?
+    .read_to_end(&mut png)
+    .ok()?;
+
+  Some(format!("data:image/png;base64,{}", BASE64.encode(png)))
+}
+
+// GZDoom ends the comment with the level time, eg `Time: 00:12:34`. The label
+// is localized, so only the trailing `HH:MM:SS` is looked at.
+fn parse_zds_play_time(comment: &str) -> Option<i32> {
+  let time = comment.split_whitespace().last()?;
+  let mut parts = time.split(':').map(|x| x.parse::<i32>());
+
+  let hours = parts.next()?.ok()?;
+  let minutes = parts.next()?.ok()?;
+  let seconds = parts.next()?.ok()?;
+
+  if parts.next().is_some() {
+    return None;
+  }
+
+  Some(hours * 3600 + minutes * 60 + seconds)
+}
+
+/// Lowercased file names of the WADs a save was made with, for formats that
+/// record them.
+pub fn read_save_wad_names(path: &Path) -> Vec<String> {
+  let extension = path
+    .extension()
+    .and_then(|x| x.to_str())
+    .unwrap_or_default()
+    .to_lowercase();
+
+  let wad_paths = if extension == "zds" {
+    read_zds_info(path)
+      .map(|x| x.game_wad.into_iter().chain(x.map_wad).collect())
+      .unwrap_or_default()
+  } else if extension == "dsg" {
+    read_dsg_wad_list(path).unwrap_or_default()
+  } else {
+    vec![]
+  };
+
+  let mut wad_names: Vec<String> = wad_paths
+    .iter()
+    .filter_map(|x| x.rsplit(['/', '\\']).next())
+    .filter(|x| !x.is_empty())
+    .map(|x| x.to_lowercase())
+    .collect();
+
+  wad_names.sort();
+  wad_names.dedup();
+
+  wad_names
+}
+
+fn read_dsg_wad_list(path: &Path) -> Option<Vec<String>> {
+  let mut buffer = vec![];
+  File::open(path)
+    .ok()?
+    .take(DSG_WAD_LIST_OFFSET as u64 + 4096)
+    .read_to_end(&mut buffer)
+    .ok()?;
+
+  let wad_list = buffer.get(DSG_WAD_LIST_OFFSET..)?;
+  let end = wad_list.iter().position(|x| *x == 0)?;
+  let wad_list = std::str::from_utf8(&wad_list[..end]).ok()?;
+
+  // Vanilla-style saves have game state here instead, which won't look like a
+  // list of file names.
+  let wad_paths: Vec<String> = wad_list
+    .split('\n')
+    .filter(|x| !x.is_empty())
+    .map(|x| x.to_string())
+    .collect();
+  let looks_like_wad_list = !wad_paths.is_empty()
+    && wad_paths
+      .iter()
+      .all(|x| !x.chars().any(|c| c.is_control()) && x.contains('.'));
+
+  if looks_like_wad_list {
+    Some(wad_paths)
+  } else {
+    None
+  }
+}
+
+/// Copies saves from every known source port's default save directories, plus
+/// the save folders of portable source port installs, into the game's saves
+/// directory. Only saves that record the WADs they were made with, and
+/// whose WADs match the game's files, are copied.
+pub fn import_source_port_saves(
+  store: &dyn LibraryStore,
+  game_id: &str,
+) -> Result<Vec<SaveGame>, WadpunkError> {
+  let db_game = store.load_game_meta(game_id)?;
+  let game_file_names: Vec<String> = store
+    .find_all_game_files(game_id)
+    .iter()
+    .filter_map(|x| Path::new(&x.path).file_name())
+    .map(|x| x.to_string_lossy().to_lowercase())
+    .collect();
+
+  let mut search_directories: Vec<PathBuf> = get_all_known_source_ports()
+    .iter()
+    .flat_map(|x| x.default_save_directories())
+    .collect();
+
+  search_directories.sort();
+  search_directories.dedup();
+
+  let mut candidates: Vec<PathBuf> = vec![];
+  for search_directory in search_directories {
+    find_save_files(&search_directory, SAVE_SCAN_DEPTH, &mut candidates);
+  }
+
+  for db_source_port in store.find_all_source_ports()? {
+    if let Some(executable) = db_source_port.command.and_then(|x| x.into_iter().next()) {
+      if let Some(parent) = Path::new(&executable).parent() {
+        find_portable_save_files(parent, &mut candidates);
+      }
+    }
+  }
+
+  candidates.sort();
+  candidates.dedup();
+
+  let saves_directory = database::get_game_saves_directory(game_id);
+  let mut imported: Vec<SaveGame> = vec![];
+
+  for candidate in candidates {
+    let wad_names = read_save_wad_names(&candidate);
+
+    if wad_names.is_empty() {
+      continue;
+    }
+
+    // IWADs need every WAD in the save to be theirs, otherwise any save made
+    // with a PWAD on top would match too.
+    let is_match = if db_game.is_iwad() {
+      wad_names.iter().all(|x| game_file_names.contains(x))
+    } else {
+      wad_names.iter().any(|x| game_file_names.contains(x))
+    };
+
+    if !is_match {
+      continue;
+    }
+
+    if let Some(destination) = find_import_destination(&candidate, &saves_directory)? {
+      fs::create_dir_all(&saves_directory)?;
+      fs::copy(&candidate, &destination)?;
+
+      imported.push(load_save_game(&destination));
+    }
+  }
+
+  Ok(imported)
+}
+
+fn find_save_files(directory: &Path, depth: usize, files: &mut Vec<PathBuf>) {
+  let Ok(entries) = fs::read_dir(directory) else {
+    return;
+  };
+
+  for entry in entries.flatten() {
+    let path = entry.path();
+
+    if path.is_dir() {
+      if depth > 0 {
+        find_save_files(&path, depth - 1, files);
+      }
+    } else if path
+      .extension()
+      .and_then(|x| x.to_str())
+      .map(|x| SAVE_EXTENSIONS.contains(&x.to_lowercase().as_str()))
+      .unwrap_or_default()
+    {
+      files.push(path);
+    }
+  }
+}
+
+/// Portable installs keep saves next to the executable. Installed ones can be
+/// in something like `/usr/bin`, so only the folder itself and the save
+/// folders in it are looked through, not everything below it.
+fn find_portable_save_files(directory: &Path, files: &mut Vec<PathBuf>) {
+  find_save_files(directory, 0, files);
+
+  let Ok(entries) = fs::read_dir(directory) else {
+    return;
+  };
+
+  for entry in entries.flatten() {
+    let path = entry.path();
+
+    let is_save_folder = path.is_dir()
+      && path
+        .file_name()
+        .and_then(|x| x.to_str())
+        .map(|x| PORTABLE_SAVE_FOLDERS.contains(&x.to_lowercase().as_str()))
+        .unwrap_or_default();
+
+    if is_save_folder {
+      find_save_files(&path, SAVE_SCAN_DEPTH, files);
+    }
+  }
+}
+
+/// Where to copy a save to, or `None` if an identical copy is already there.
+/// Differing saves with the same name get a numeric suffix.
+fn find_import_destination(source: &Path, saves_directory: &Path) -> io::Result<Option<PathBuf>> {
+  let contents = fs::read(source)?;
+  let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+  let extension = source
+    .extension()
+    .map(|x| format!(".{}", x.to_string_lossy()))
+    .unwrap_or_default();
+
+  for i in 0..=u32::MAX {
+    let file_name = if i == 0 {
+      format!("{}{}", stem, extension)
+    } else {
+      format!("{}-{}{}", stem, i, extension)
+    };
+    let destination = saves_directory.join(file_name);
+
+    if !destination.exists() {
+      return Ok(Some(destination));
+    }
+
+    if fs::read(&destination)? == contents {
+      return Ok(None);
+    }
+  }
+
+  Err(io::Error::new(
+    io::ErrorKind::AlreadyExists,
+    format!("{} has no free name left in {:?}", stem, saves_directory),
+  ))
+}
+
+fn read_dsg_description(path: &Path) -> Option<String> {
+  let mut buffer = [0u8; DSG_DESCRIPTION_SIZE];
+  File::open(path).ok()?.read_exact(&mut buffer).ok()?;
+
+  let end = buffer.iter().position(|x| *x == 0).unwrap_or(buffer.len());
+  let description = String::from_utf8_lossy(&buffer[..end]).trim().to_string();
+
+  if description.is_empty() {
+    None
+  } else {
+    Some(description)
+  }
+}
+
+pub fn get_game_save_snapshots_directory(game_id: &str) -> PathBuf {
+  database::get_meta_directory()
+    .join(database::normalize_name_from_id(game_id))
+    .join("saveSnapshots")
+}
+
+pub fn find_all_save_snapshots(game_id: &str) -> Vec<SaveSnapshot> {
+  let snapshots_directory = get_game_save_snapshots_directory(game_id);
+
+  if !snapshots_directory.exists() {
+    return vec![];
+  }
+
+  let mut snapshots: Vec<SaveSnapshot> = vec![];
+
+  for disk_entry in read_dir(snapshots_directory, false).unwrap_or_default() {
+    let Some(file_name) = disk_entry.name else {
+      continue;
+    };
+
+    if file_name.starts_with('.') || !file_name.ends_with(".zip") {
+      continue;
+    }
+
+    let size = fs::metadata(&disk_entry.path)
+      .map(|x| x.len())
+      .unwrap_or_default();
+    let file_count = File::open(&disk_entry.path)
+      .ok()
+      .and_then(|x| zip::ZipArchive::new(x).ok())
+      .map(|x| x.len())
+      .unwrap_or_default();
+
+    snapshots.push(SaveSnapshot {
+      created_at: parse_snapshot_id(&file_name)
+        .map(|x| x.to_rfc3339())
+        .unwrap_or_default(),
+      id: file_name,
+      file_count: file_count.try_into().unwrap_or(i32::MAX),
+      size: size.try_into().unwrap_or(i32::MAX),
+    });
+  }
+
+  // Ids are timestamps, so sorting them puts the newest first.
+  snapshots.sort_by(|a, b| b.id.cmp(&a.id));
+
+  snapshots
+}
+
+/// Archives the game's saves directory, then deletes the oldest snapshots past
+/// `MAX_SAVE_SNAPSHOTS`. Nothing is written if there are no saves, or none
+/// have changed since the latest snapshot.
+pub fn snapshot_game_saves(game_id: &str) -> Result<Option<String>, WadpunkError> {
+  let saves_directory = database::get_game_saves_directory(game_id);
+  let snapshots_directory = get_game_save_snapshots_directory(game_id);
+
+  if !saves_directory.exists() {
+    return Ok(None);
+  }
+
+  let mut files: Vec<String> = vec![];
+  for disk_entry in read_dir(&saves_directory, true)? {
+    database::recurse_disk_entry(disk_entry, &mut files);
+  }
+
+  if files.is_empty() {
+    return Ok(None);
+  }
+
+  let last_modified_at = files
+    .iter()
+    .filter_map(|x| fs::metadata(x).and_then(|x| x.modified()).ok())
+    .max();
+  let last_snapshot_at = find_all_save_snapshots(game_id)
+    .first()
+    .and_then(|x| fs::metadata(snapshots_directory.join(&x.id)).ok())
+    .and_then(|x| x.modified().ok());
+
+  if let (Some(last_modified_at), Some(last_snapshot_at)) = (last_modified_at, last_snapshot_at) {
+    if last_modified_at <= last_snapshot_at {
+      return Ok(None);
+    }
+  }
+
+  fs::create_dir_all(&snapshots_directory)?;
+
+  // Also the snapshot's file name.
+  let snapshot_id = format!("{}.zip", database::format_file_name_time(&Utc::now()));
+  let mut archive = zip::ZipWriter::new(
+    File::options()
+      .write(true)
+      .create_new(true)
+      .open(snapshots_directory.join(&snapshot_id))?,
+  );
+  let options =
+    zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+  for file in files {
+    let relative = Path::new(&file)
+      .strip_prefix(&saves_directory)
+      .unwrap()
+      .components()
+      .map(|x| x.as_os_str().to_string_lossy())
+      .collect::<Vec<_>>()
+      .join("/");
+
+    archive.start_file(relative, options)?;
+    archive.write_all(&fs::read(&file)?)?;
+  }
+
+  archive.finish()?;
+
+  for snapshot in find_all_save_snapshots(game_id)
+    .into_iter()
+    .skip(MAX_SAVE_SNAPSHOTS)
+  {
+    fs::remove_file(snapshots_directory.join(snapshot.id))?;
+  }
+
+  Ok(Some(snapshot_id))
+}
+
+/// Replaces the game's saves directory with the contents of a snapshot. The
+/// current saves are snapshotted first so a restore can itself be undone.
+/// That can prune the snapshot being restored, so it's read before.
+pub fn restore_save_snapshot(game_id: &str, snapshot_id: &str) -> Result<(), WadpunkError> {
+  let snapshot_path = get_game_save_snapshots_directory(game_id).join(snapshot_id);
+
+  // Only accept ids as returned by `find_all_save_snapshots`.
+  if Path::new(snapshot_id).file_name().and_then(|x| x.to_str()) != Some(snapshot_id)
+    || !snapshot_path.is_file()
+  {
+    return Err(WadpunkError::NotFound(format!(
+      "save snapshot {}",
+      snapshot_id
+    )));
+  }
+
+  let mut archive = zip::ZipArchive::new(io::Cursor::new(fs::read(&snapshot_path)?))?;
+
+  snapshot_game_saves(game_id)?;
+
+  let saves_directory = database::get_game_saves_directory(game_id);
+  if saves_directory.exists() {
+    fs::remove_dir_all(&saves_directory)?;
+  }
+  fs::create_dir_all(&saves_directory)?;
+
+  archive.extract(&saves_directory)?;
+
+  Ok(())
+}
+
+fn parse_snapshot_id(snapshot_id: &str) -> Option<DateTime<Utc>> {
+  database::parse_file_name_time(snapshot_id.strip_suffix(".zip")?)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn suffixes_differing_saves_with_the_same_name() {
+    let data_directory = database::use_test_data_directory("save-import-destination");
+    let source_directory = data_directory.join("source");
+    let saves_directory = data_directory.join("saves");
+    fs::create_dir_all(&source_directory).unwrap();
+    fs::create_dir_all(&saves_directory).unwrap();
+
+    let source = source_directory.join("doomsav0.dsg");
+    fs::write(&source, "new").unwrap();
+    fs::write(saves_directory.join("doomsav0.dsg"), "old").unwrap();
+
+    assert_eq!(
+      find_import_destination(&source, &saves_directory).unwrap(),
+      Some(saves_directory.join("doomsav0-1.dsg"))
+    );
+
+    // Identical copies aren't made again.
+    fs::write(saves_directory.join("doomsav0-1.dsg"), "new").unwrap();
+    assert_eq!(
+      find_import_destination(&source, &saves_directory).unwrap(),
+      None
+    );
+
+    // Saves without an extension don't get one.
+    let source = source_directory.join("save0");
+    fs::write(&source, "new").unwrap();
+    assert_eq!(
+      find_import_destination(&source, &saves_directory).unwrap(),
+      Some(saves_directory.join("save0"))
+    );
+  }
+}
@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use serde_json::Value;
+use tauri::api::path::config_dir;
+use tauri::api::path::home_dir;
+
+use crate::database;
+use crate::error::WadpunkError;
+use crate::graphql::generated::ImportConflictPolicy;
+use crate::graphql::generated::ImportSummary;
+use crate::graphql::generated::InstalledIwad;
+use crate::graphql::generated::StoreKind;
+use crate::importer;
+use crate::library_store::LibraryStore;
+
+// Lowercase, stores ship them in whatever case they like.
+const KNOWN_IWADS: [&str; 18] = [
+  "doom.wad",
+  "doom1.wad",
+  "doom2.wad",
+  "doom2f.wad",
+  "tnt.wad",
+  "plutonia.wad",
+  "heretic.wad",
+  "heretic1.wad",
+  "hexen.wad",
+  "hexdd.wad",
+  "strife1.wad",
+  "voices.wad",
+  "chex.wad",
+  "nerve.wad",
+  "masterlevels.wad",
+  // From the 2024 Doom + Doom II re-release.
+  "id1.wad",
+  "id24res.wad",
+  "extras.wad",
+];
+
+// Folders in `steamapps/common`. Only these are looked through, libraries
+// can have hundreds of other games.
+const STEAM_GAME_FOLDERS: [&str; 11] = [
+  "Ultimate Doom",
+  "Doom 2",
+  "Final Doom",
+  "Master Levels of Doom",
+  "DOOM 3 BFG Edition",
+  "DOOM + DOOM II",
+  "Heretic Shadow of the Serpent Riders",
+  "Hexen",
+  "Hexen Deathkings of the Dark Citadel",
+  "Heretic + Hexen",
+  "Strife",
+];
+
+// Deep enough for `DOOM 3 BFG Edition/base/wads/DOOM.WAD`.
+const MAX_SCAN_DEPTH: usize = 3;
+
+/// Looks through local Steam, GOG and Heroic installs for IWADs, and the
+/// other WADs that come with them.
+pub fn find_installed_iwads(store: &dyn LibraryStore) -> Result<Vec<InstalledIwad>, WadpunkError> {
+  let mut game_folders: Vec<(StoreKind, PathBuf)> = vec![];
+
+  for library in find_steam_libraries() {
+    let common = library.join("steamapps").join("common");
+
+    game_folders.extend(
+      STEAM_GAME_FOLDERS
+        .iter()
+        .map(|x| (StoreKind::STEAM, common.join(x))),
+    );
+  }
+
+  game_folders.extend(
+    find_gog_game_folders()
+      .into_iter()
+      .map(|x| (StoreKind::GOG, x)),
+  );
+  game_folders.extend(
+    find_heroic_game_folders()
+      .into_iter()
+      .map(|x| (StoreKind::HEROIC, x)),
+  );
+
+  // Games already referencing an install, so they're matched by path rather
+  // than only by name.
+  let referenced: HashMap<PathBuf, String> = store
+    .find_all_games()?
+    .into_iter()
+    .filter_map(|x| Some((PathBuf::from(x.external_path?), x.id?)))
+    .collect();
+
+  let mut seen: HashSet<PathBuf> = HashSet::new();
+  let mut installed_iwads: Vec<InstalledIwad> = vec![];
+
+  for (store_kind, game_folder) in game_folders {
+    let mut files = vec![];
+    collect_iwads(&game_folder, MAX_SCAN_DEPTH, &mut files);
+
+    for file in files {
+      // Steam libraries and GOG folders can overlap, like a GOG game
+      // added to Steam.
+      let Ok(file) = fs::canonicalize(&file) else {
+        continue;
+      };
+      if !seen.insert(file.clone()) {
+        continue;
+      }
+
+      // Found by a name that's valid UTF-8, but a link can point anywhere.
+      let Some(file_name) = file.file_name().and_then(|x| x.to_str()) else {
+        continue;
+      };
+      let file_name = file_name.to_string();
+      let Ok(path) = database::path_to_string(&file) else {
+        continue;
+      };
+      let game_id = match referenced.get(&file) {
+        Some(game_id) => Some(game_id.clone()),
+        None => importer::find_iwad_game_id(store, &file_name.to_lowercase())?,
+      };
+
+      installed_iwads.push(InstalledIwad {
+        path,
+        game_id,
+        file_name,
+        store: store_kind,
+        is_kex: is_kex_install(&file),
+      });
+    }
+  }
+
+  Ok(installed_iwads)
+}
+
+/// Adds IWADs found by `find_installed_iwads` to the library, either as
+/// copies or referenced where they are.
+pub fn register_iwads(
+  store: &dyn LibraryStore,
+  paths: &[String],
+  reference: bool,
+  seven_zip_path: &str,
+  conflict_policy: ImportConflictPolicy,
+) -> ImportSummary {
+  let mut summary = ImportSummary {
+    imported_count: 0,
+    skipped_count: 0,
+    failed_count: 0,
+    game_ids: vec![],
+    warnings: vec![],
+    errors: vec![],
+  };
+
+  for path in paths {
+    let result = if reference {
+      importer::reference_file(store, path, conflict_policy)
+    } else {
+      importer::import_file(store, path, seven_zip_path, conflict_policy, false)
+    };
+
+    match result {
+      Ok(import_result) => {
+        summary.warnings.extend(
+          import_result
+            .warnings
+            .iter()
+            .map(|x| format!("{}: {}", path, x)),
+        );
+
+        if import_result.skipped {
+          summary.skipped_count += 1;
+        } else {
+          summary.imported_count += 1;
+        }
+        summary.game_ids.extend(import_result.game_id);
+      }
+      Err(err) => {
+        summary.failed_count += 1;
+        summary.errors.push(format!("{}: {}", path, err));
+      }
+    }
+  }
+
+  summary
+}
+
+fn collect_iwads(directory: &Path, depth: usize, files: &mut Vec<PathBuf>) {
+  let Ok(entries) = fs::read_dir(directory) else {
+    return;
+  };
+
+  for entry in entries.flatten() {
+    let path = entry.path();
+
+    if path.is_dir() {
+      if depth > 0 {
+        collect_iwads(&path, depth - 1, files);
+      }
+    } else if let Some(file_name) = path.file_name().and_then(|x| x.to_str()) {
+      if KNOWN_IWADS.contains(&file_name.to_lowercase().as_str()) {
+        files.push(path);
+      }
+    }
+  }
+}
+
+/// The re-release keeps its IWADs in `rerelease`, next to `id1.wad`. They're
+/// different from the original versions, so both are worth having.
+fn is_kex_install(file: &Path) -> bool {
+  let Some(directory) = file.parent() else {
+    return false;
+  };
+
+  let is_in_rerelease = directory
+    .file_name()
+    .and_then(|x| x.to_str())
+    .map(|x| x.eq_ignore_ascii_case("rerelease"))
+    .unwrap_or_default();
+
+  let has_id1 = fs::read_dir(directory)
+    .map(|entries| {
+      entries.flatten().any(|x| {
+        x.file_name()
+          .to_str()
+          .map(|x| x.eq_ignore_ascii_case("id1.wad"))
+          .unwrap_or_default()
+      })
+    })
+    .unwrap_or_default();
+
+  is_in_rerelease || has_id1
+}
+
+fn find_steam_libraries() -> Vec<PathBuf> {
+  let home = home_dir().unwrap_or_default();
+
+  let steam_directories = [
+    home.join(".steam").join("steam"),
+    home.join(".local").join("share").join("Steam"),
+    home.join(".var/app/com.valvesoftware.Steam/.local/share/Steam"),
+    home.join("Library/Application Support/Steam"),
+    PathBuf::from("C:\\Program Files (x86)\\Steam"),
+    PathBuf::from("C:\\Program Files\\Steam"),
+  ];
+
+  let mut libraries: Vec<PathBuf> = vec![];
+
+  for steam_directory in steam_directories {
+    if !steam_directory.is_dir() {
+      continue;
+    }
+
+    let library_folders_path = steam_directory.join("steamapps").join("libraryfolders.vdf");
+    let library_folders = fs::read_to_string(library_folders_path).unwrap_or_default();
+
+    // The install folder is a library even when the file is missing.
+    libraries.push(steam_directory);
+    libraries.extend(parse_library_folders(&library_folders));
+  }
+
+  libraries
+}
+
+/// Only the `"path"` values are needed, so there's no full VDF parser.
+///
+/// ```text
+/// "libraryfolders"
+/// {
+///   "1"
+///   {
+///     "path"    "D:\\SteamLibrary"
+/// ```
+fn parse_library_folders(contents: &str) -> Vec<PathBuf> {
+  contents
+    .lines()
+    .filter_map(|line| {
+      let mut values = line.split('"').skip(1).step_by(2);
+
+      match (values.next(), values.next()) {
+        (Some(key), Some(value)) if key.eq_ignore_ascii_case("path") => {
+          Some(PathBuf::from(value.replace("\\\\", "\\")))
+        }
+        _ => None,
+      }
+    })
+    .collect()
+}
+
+/// GOG Galaxy and the offline installers both default to one folder per game.
+fn find_gog_game_folders() -> Vec<PathBuf> {
+  let home = home_dir().unwrap_or_default();
+
+  let gog_directories = [
+    home.join("GOG Games"),
+    PathBuf::from("C:\\GOG Games"),
+    PathBuf::from("C:\\Program Files (x86)\\GOG Galaxy\\Games"),
+  ];
+
+  gog_directories
+    .iter()
+    .filter_map(|x| fs::read_dir(x).ok())
+    .flat_map(|entries| entries.flatten().map(|x| x.path()))
+    .filter(|x| x.is_dir())
+    .collect()
+}
+
+/// Heroic keeps a list of installed GOG games, each with its install path.
+fn find_heroic_game_folders() -> Vec<PathBuf> {
+  let home = home_dir().unwrap_or_default();
+  let config = config_dir().unwrap_or_default();
+
+  let installed_paths = [
+    config.join("heroic/gog_store/installed.json"),
+    home.join(".var/app/com.heroicgameslauncher.hgl/config/heroic/gog_store/installed.json"),
+  ];
+
+  let mut game_folders: Vec<PathBuf> = vec![];
+
+  for installed_path in installed_paths {
+    let Ok(contents) = fs::read_to_string(installed_path) else {
+      continue;
+    };
+    let Ok(json) = serde_json::from_str::<Value>(&contents) else {
+      continue;
+    };
+
+    let installed = json["installed"].as_array().cloned().unwrap_or_default();
+
+    game_folders.extend(
+      installed
+        .iter()
+        .filter_map(|x| x["install_path"].as_str())
+        .map(PathBuf::from),
+    );
+  }
+
+  game_folders
+}
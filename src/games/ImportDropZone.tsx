@@ -7,9 +7,10 @@ import {
   Snackbar,
   Stack,
 } from '@mui/material'
-import { useState } from 'react'
+import { listen } from '@tauri-apps/api/event'
+import { useEffect, useState } from 'react'
 
-import { ImportFileDocument } from '#src/app/operations.generated'
+import { ImportFilesDocument } from '#src/app/operations.generated'
 import { invalidateApolloQuery } from '#src/graphql/graphqlClient'
 import { useI18nContext } from '#src/i18n/lib/i18nContext'
 import basename from '#src/lib/basename'
@@ -23,34 +24,67 @@ interface ImportStatus {
   status: 'importing' | 'done'
 }
 
+// Mirrors `ImportProgress` in `importer.rs`.
+interface ImportProgressPayload {
+  file_path: string
+  index: number
+  total: number
+  bytes_processed: number
+  total_bytes: number
+  status: 'importing' | 'imported' | 'skipped' | 'failed'
+  game_id: string | null
+  error: string | null
+}
+
 const ImportDropZone: React.FC<React.PropsWithChildren> = (props) => {
   const [currentImportStatus, setCurrentImportStatus] = useState<
     ImportStatus | undefined
   >()
-  const [importFile] = useMutation(ImportFileDocument)
+  const [importFiles] = useMutation(ImportFilesDocument)
   const isSourcePortsDialogOpen = useRootSelector(
     (state) => state.sourcePorts.isDialogOpen,
   )
   const { t } = useI18nContext()
 
+  useEffect(() => {
+    const stopListeningProgress = listen<ImportProgressPayload>(
+      'import-progress',
+      (event) => {
+        if (event.payload.status !== 'importing') {
+          return
+        }
+
+        setCurrentImportStatus({
+          currentFilePath: event.payload.file_path,
+          index: event.payload.index,
+          length: event.payload.total,
+          status: 'importing',
+        })
+      },
+    )
+
+    // Files picked up from an inbox folder, see `inbox_watcher.rs`.
+    const stopListeningInbox = listen<{ game_id: string | null }>(
+      'inbox-import',
+      (event) => {
+        if (event.payload.game_id) {
+          invalidateApolloQuery(['getGames'])
+        }
+      },
+    )
+
+    return () => {
+      stopListeningProgress.then((unlisten) => unlisten())
+      stopListeningInbox.then((unlisten) => unlisten())
+    }
+  }, [])
+
   const tauriFileDrop = useTauriFileDrop(async (event) => {
     if (isSourcePortsDialogOpen) {
       return
     }
 
-    let i = 0
-    for (const file of event.payload) {
-      setCurrentImportStatus({
-        currentFilePath: file,
-        index: i,
-        length: event.payload.length,
-        status: 'importing',
-      })
-
-      await importFile({ variables: { file_path: file } })
-
-      i++
-    }
+    await importFiles({ variables: { file_paths: event.payload } })
 
     invalidateApolloQuery(['getGames'])
 